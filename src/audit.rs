@@ -0,0 +1,177 @@
+use crate::fs::Fs;
+use crate::{Args, CHECKSUM_FILE_NAME};
+use chrono::format::{parse, Parsed, StrftimeItems};
+use chrono::Datelike;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Year and month, e.g. `(2024, 3)`.
+pub(crate) type YearMonth = (i32, u32);
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct MonthStats {
+    pub(crate) files: u64,
+    pub(crate) bytes: u64,
+}
+
+/// Result of `--report-only`, see [`audit_target`].
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct AuditReport {
+    pub(crate) months: BTreeMap<YearMonth, MonthStats>,
+    /// Months without files between the first and the last populated month.
+    pub(crate) gaps: Vec<YearMonth>,
+    /// Files whose name does not match the target file pattern.
+    pub(crate) unexpected_names: Vec<PathBuf>,
+}
+
+/// Scans the target directory and groups files by the date parsed from their names.
+/// The unrecognized folder and checksum files are not part of the report.
+pub(crate) fn audit_target(args: &Args, fs: &dyn Fs) -> anyhow::Result<AuditReport> {
+    let unrecognized_root = args.unrecognized.parent();
+    let mut report = AuditReport::default();
+    for entry in fs.list_dir(&args.target, &args.excluded_dirs)? {
+        let entry = entry?;
+        if entry.metadata.is_dir()
+            || unrecognized_root.is_some_and(|root| entry.path.starts_with(root))
+            || entry
+                .path
+                .file_name()
+                .is_some_and(|name| name == CHECKSUM_FILE_NAME)
+        {
+            continue;
+        }
+        let (_, file_pattern) = args.patterns_for(&entry.path);
+        match parse_year_month(&entry.path, file_pattern) {
+            Some(year_month) => {
+                let stats = report.months.entry(year_month).or_default();
+                stats.files += 1;
+                stats.bytes += entry.metadata.len();
+            }
+            None => report.unexpected_names.push(entry.path),
+        }
+    }
+    report.unexpected_names.sort();
+    report.gaps = find_gaps(&report.months);
+    Ok(report)
+}
+
+/// Parses the file stem with the pattern, allowing the `_N` suffix added on name collisions.
+fn parse_year_month(path: &Path, file_pattern: &str) -> Option<YearMonth> {
+    let stem = path.file_stem()?.to_str()?;
+    let without_index = stem
+        .rsplit_once('_')
+        .filter(|(_, index)| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+        .map(|(base, _)| base);
+    std::iter::once(stem).chain(without_index).find_map(|name| {
+        let mut parsed = Parsed::new();
+        parse(&mut parsed, name, StrftimeItems::new(file_pattern)).ok()?;
+        match parsed.to_naive_date() {
+            Ok(date) => Some((date.year(), date.month())),
+            Err(_) => Some((parsed.year()?, parsed.month()?)),
+        }
+    })
+}
+
+fn find_gaps(months: &BTreeMap<YearMonth, MonthStats>) -> Vec<YearMonth> {
+    let (Some(first), Some(last)) = (months.keys().next(), months.keys().next_back()) else {
+        return Vec::new();
+    };
+    let mut gaps = Vec::new();
+    let (mut year, mut month) = *first;
+    while (year, month) < *last {
+        if !months.contains_key(&(year, month)) {
+            gaps.push((year, month));
+        }
+        (year, month) = if month == 12 {
+            (year + 1, 1)
+        } else {
+            (year, month + 1)
+        };
+    }
+    gaps
+}
+
+pub(crate) fn print_audit_report(args: &Args, report: &AuditReport) {
+    info!("Audit of [{}]:", args.fmt_path(&args.target));
+    for ((year, month), stats) in &report.months {
+        info!(
+            "{year:04}-{month:02} {:>10} files {:>15} bytes",
+            stats.files, stats.bytes
+        );
+    }
+    let files: u64 = report.months.values().map(|stats| stats.files).sum();
+    let bytes: u64 = report.months.values().map(|stats| stats.bytes).sum();
+    info!("Total: {files} files, {bytes} bytes");
+    if report.gaps.is_empty() {
+        info!("Months without files: none");
+    } else {
+        let gaps: Vec<String> = report
+            .gaps
+            .iter()
+            .map(|(year, month)| format!("{year:04}-{month:02}"))
+            .collect();
+        info!("Months without files: {}", gaps.join(", "));
+    }
+    info!(
+        "Files not matching the naming pattern: {}",
+        report.unexpected_names.len()
+    );
+    for path in &report.unexpected_names {
+        info!("{}", args.fmt_path(path));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{date, TestEnv};
+
+    #[test]
+    fn audit_of_the_target() {
+        let env = TestEnv::new(&[]);
+        let day = date("2024-01-01T00:00:00Z");
+        env.add_file("/dst/2023/12/31/2023-12-31T235959.jpg", 5, day);
+        env.add_file("/dst/2024/01/01/2024-01-01T000000.jpg", 10, day);
+        env.add_file("/dst/2024/01/02/2024-01-02T000000_1.jpg", 20, day);
+        env.add_file("/dst/2024/03/01/2024-03-01T120000.mp4", 30, day);
+        env.add_file("/dst/2024/03/01/IMG_0001.jpg", 40, day);
+        env.add_file("/dst/2024/01/01/SHA256SUMS", 50, day);
+        env.add_file("/dst/unrecognized/2024-05-01T000000/notes.txt", 60, day);
+
+        let report = audit_target(&env.args, env.args.fs.as_ref()).unwrap();
+        assert_eq!(
+            report.months,
+            BTreeMap::from([
+                ((2023, 12), MonthStats { files: 1, bytes: 5 }),
+                (
+                    (2024, 1),
+                    MonthStats {
+                        files: 2,
+                        bytes: 30
+                    }
+                ),
+                (
+                    (2024, 3),
+                    MonthStats {
+                        files: 1,
+                        bytes: 30
+                    }
+                ),
+            ])
+        );
+        assert_eq!(report.gaps, [(2024, 2)]);
+        assert_eq!(
+            report.unexpected_names,
+            [PathBuf::from("/dst/2024/03/01/IMG_0001.jpg")]
+        );
+    }
+
+    #[test]
+    fn audit_of_an_empty_target() {
+        let env = TestEnv::new(&[]);
+        env.add_dir("/dst");
+        let report = audit_target(&env.args, env.args.fs.as_ref()).unwrap();
+        assert_eq!(report, AuditReport::default());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::output::OutputEncoding;
+use crate::{ConflictStrategy, DateSource, RawArgs, UnknownLogFormat};
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+use tracing::Level;
+
+/// Options loaded from a `--config` TOML file.
+/// Mirrors [`RawArgs`], but every field is optional so that only the values present in the file are applied.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawArgsConfig {
+    unrecognized: Option<String>,
+    target_dir_pattern: Option<String>,
+    target_file_pattern: Option<String>,
+    ext_pattern: Option<Vec<String>>,
+    date_source: Option<String>,
+    conflict_strategy: Option<String>,
+    interactive: Option<bool>,
+    log_level: Option<String>,
+    verbose: Option<bool>,
+    use_reflink: Option<bool>,
+    dry_run: Option<bool>,
+    rename_only: Option<bool>,
+    #[serde(rename = "move")]
+    move_files: Option<bool>,
+    copy_symlinks_as_symlinks: Option<bool>,
+    watch: Option<bool>,
+    two_pass: Option<bool>,
+    report_only: Option<bool>,
+    skip_unrecognized: Option<bool>,
+    exclude_dir: Option<Vec<String>>,
+    source_filter: Option<Vec<String>>,
+    source_exclude: Option<Vec<String>>,
+    flat: Option<bool>,
+    preserve_extension_case: Option<bool>,
+    size_min: Option<u64>,
+    size_max: Option<u64>,
+    min_free_space: Option<u64>,
+    generate_checksums: Option<bool>,
+    max_files: Option<u64>,
+    max_bytes: Option<u64>,
+    dir_stats: Option<bool>,
+    perf: Option<bool>,
+    dry_run_save: Option<String>,
+    dry_run_diff: Option<String>,
+    unknown_log_format: Option<String>,
+    output_encoding: Option<String>,
+}
+
+impl RawArgsConfig {
+    pub(crate) fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file [{}]", path.display()))?;
+        Self::from_toml(&content)
+            .with_context(|| format!("Failed to parse config file [{}]", path.display()))
+    }
+
+    fn from_toml(s: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+
+    /// Fills in the values that were not given on the command line.
+    /// Switches can only be turned on by the config, and lists are used only if the option was not specified at all.
+    pub(crate) fn apply(self, args: &mut RawArgs) -> anyhow::Result<()> {
+        fn or<T>(arg: &mut Option<T>, value: Option<T>) {
+            if arg.is_none() {
+                *arg = value;
+            }
+        }
+        fn or_list(arg: &mut Vec<String>, value: Option<Vec<String>>) {
+            if arg.is_empty() {
+                *arg = value.unwrap_or_default();
+            }
+        }
+        fn or_switch(arg: &mut bool, value: Option<bool>) {
+            *arg |= value.unwrap_or(false);
+        }
+
+        let date_source = self
+            .date_source
+            .map(|s| DateSource::from_str(&s).map_err(|e| anyhow!(e)))
+            .transpose()?;
+        let log_level = self
+            .log_level
+            .map(|s| Level::from_str(&s).with_context(|| format!("Invalid log level [{s}]")))
+            .transpose()?;
+        let conflict_strategy = self
+            .conflict_strategy
+            .map(|s| ConflictStrategy::from_str(&s).map_err(|e| anyhow!(e)))
+            .transpose()?;
+        let unknown_log_format = self
+            .unknown_log_format
+            .map(|s| UnknownLogFormat::from_str(&s).map_err(|e| anyhow!(e)))
+            .transpose()?;
+        let output_encoding = self
+            .output_encoding
+            .map(|s| OutputEncoding::from_str(&s).map_err(|e| anyhow!(e)))
+            .transpose()?;
+
+        or(&mut args.unrecognized, self.unrecognized);
+        or(&mut args.target_dir_pattern, self.target_dir_pattern);
+        or(&mut args.target_file_pattern, self.target_file_pattern);
+        or_list(&mut args.ext_pattern, self.ext_pattern);
+        or(&mut args.date_source, date_source);
+        or(&mut args.conflict_strategy, conflict_strategy);
+        or_switch(&mut args.interactive, self.interactive);
+        or(&mut args.log_level, log_level);
+        or_switch(&mut args.verbose, self.verbose);
+        or_switch(&mut args.use_reflink, self.use_reflink);
+        or_switch(&mut args.dry_run, self.dry_run);
+        or_switch(&mut args.rename_only, self.rename_only);
+        or_switch(&mut args.move_files, self.move_files);
+        or_switch(
+            &mut args.copy_symlinks_as_symlinks,
+            self.copy_symlinks_as_symlinks,
+        );
+        or_switch(&mut args.watch, self.watch);
+        or_switch(&mut args.two_pass, self.two_pass);
+        or_switch(&mut args.report_only, self.report_only);
+        or_switch(&mut args.skip_unrecognized, self.skip_unrecognized);
+        or_list(&mut args.exclude_dir, self.exclude_dir);
+        or_list(&mut args.source_filter, self.source_filter);
+        or_list(&mut args.source_exclude, self.source_exclude);
+        or_switch(&mut args.flat, self.flat);
+        or_switch(
+            &mut args.preserve_extension_case,
+            self.preserve_extension_case,
+        );
+        or(&mut args.size_min, self.size_min);
+        or(&mut args.size_max, self.size_max);
+        or(&mut args.min_free_space, self.min_free_space);
+        or_switch(&mut args.generate_checksums, self.generate_checksums);
+        or(&mut args.max_files, self.max_files);
+        or(&mut args.max_bytes, self.max_bytes);
+        or_switch(&mut args.dir_stats, self.dir_stats);
+        or_switch(&mut args.perf, self.perf);
+        or(&mut args.dry_run_save, self.dry_run_save);
+        or(&mut args.dry_run_diff, self.dry_run_diff);
+        or(&mut args.unknown_log_format, unknown_log_format);
+        or(&mut args.output_encoding, output_encoding);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::stat::Stats;
+    use crate::fs::StdFs;
+    use crate::Args;
+    use argh::FromArgs;
+    use std::rc::Rc;
+
+    fn load(toml: &str) -> RawArgsConfig {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("media-sync.toml");
+        std::fs::write(&path, toml).unwrap();
+        RawArgsConfig::load(&path).unwrap()
+    }
+
+    fn raw_args(cli: &[&str]) -> RawArgs {
+        RawArgs::from_args(&["media-sync"], cli).unwrap()
+    }
+
+    #[test]
+    fn config_overrides_the_defaults() {
+        let config = load(
+            r#"
+            target_dir_pattern = "%Y/%m"
+            exclude_dir = ["@eaDir", ".git"]
+            flat = true
+            "#,
+        );
+        let mut raw = raw_args(&["/src", "/dst"]);
+        config.apply(&mut raw).unwrap();
+        assert_eq!(raw.exclude_dir, ["@eaDir", ".git"]);
+        assert!(raw.flat);
+
+        let args = Args::new(raw, Box::new(StdFs), Rc::new(Stats::default())).unwrap();
+        assert_eq!(args.target_dir_pattern, "%Y/%m");
+    }
+
+    #[test]
+    fn command_line_takes_precedence() {
+        let config = load(
+            r#"
+            target_dir_pattern = "%Y/%m"
+            exclude_dir = ["@eaDir"]
+            "#,
+        );
+        let mut raw = raw_args(&[
+            "/src",
+            "/dst",
+            "--target-dir-pattern",
+            "%Y",
+            "--exclude-dir",
+            ".git",
+        ]);
+        config.apply(&mut raw).unwrap();
+        assert_eq!(raw.target_dir_pattern.as_deref(), Some("%Y"));
+        assert_eq!(raw.exclude_dir, [".git"]);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(RawArgsConfig::from_toml("target_pattern = \"%Y\"").is_err());
+    }
+}
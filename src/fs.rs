@@ -1,39 +1,94 @@
 pub(crate) mod cow;
 pub(crate) mod dry;
+mod error;
+pub(crate) mod limited;
 pub(crate) mod metadata;
 pub(crate) mod stat;
 
-use anyhow::Context;
 pub(crate) use metadata::Metadata;
-use std::path::Path;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
 pub(crate) use dry::DryFs;
+pub(crate) use error::{FsError, FsResult};
+
+pub(crate) type DirIterator = Box<dyn Iterator<Item = FsResult<DirEntry>>>;
+
+/// Entry returned by [`Fs::list_dir`].
+#[derive(Debug, Clone)]
+pub(crate) struct DirEntry {
+    pub path: PathBuf,
+    pub metadata: Metadata,
+}
 
 pub(crate) trait Fs {
     fn name(&self) -> String;
-    fn create_dir_all(&self, path: &Path) -> anyhow::Result<()>;
-    fn metadata(&self, path: &Path) -> anyhow::Result<Metadata>;
-    fn copy(&self, from: &Path, to: &Path) -> anyhow::Result<u64>;
+    fn create_dir_all(&self, path: &Path) -> FsResult<()>;
+    fn metadata(&self, path: &Path) -> FsResult<Metadata>;
+    fn copy(&self, from: &Path, to: &Path) -> FsResult<u64>;
+    /// Copies the file like [`Fs::copy`] and returns the SHA-256 of its content, hashed while copying where possible.
+    fn copy_with_sha256(&self, from: &Path, to: &Path) -> FsResult<(u64, String)>;
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()>;
+    /// Moves the file like [`Fs::rename`], but also works across devices by falling back to copy and delete.
+    fn move_file(&self, from: &Path, to: &Path) -> FsResult<()>;
+    /// Creates a symbolic link at `link` pointing to the `original` file.
+    fn symlink(&self, original: &Path, link: &Path) -> FsResult<()>;
+    /// Renames `from` to `to`, atomically replacing `to` if it already exists.
+    fn replace(&self, from: &Path, to: &Path) -> FsResult<()>;
+    /// Writes tool-generated content like checksum files, replacing the file if it exists.
+    fn write_bytes(&self, path: &Path, data: &[u8]) -> FsResult<()>;
+    /// Recursively lists all entries under `path`, including `path` itself.
+    /// Directories named as one of `excluded_dirs` are skipped together with their content.
+    fn list_dir(&self, path: &Path, excluded_dirs: &HashSet<String>) -> FsResult<DirIterator>;
     fn exists(&self, path: &Path) -> bool;
+    /// Returns the SHA-256 of the file content as a lowercase hex string.
+    fn sha256(&self, path: &Path) -> FsResult<String>;
+    /// Reads tool-generated content like checksum files.
+    fn read_to_string(&self, path: &Path) -> FsResult<String>;
+    /// Returns the absolute path of the file, with all symlinks resolved.
+    fn canonicalize(&self, path: &Path) -> FsResult<PathBuf>;
 }
 
 pub(crate) trait ReadonlyFs {
     fn name(&self) -> String;
-    fn metadata(&self, path: &Path) -> anyhow::Result<Metadata>;
+    fn metadata(&self, path: &Path) -> FsResult<Metadata>;
+    fn list_dir(&self, path: &Path, excluded_dirs: &HashSet<String>) -> FsResult<DirIterator>;
     fn exists(&self, path: &Path) -> bool;
+    fn sha256(&self, path: &Path) -> FsResult<String>;
+    fn read_to_string(&self, path: &Path) -> FsResult<String>;
+    fn canonicalize(&self, path: &Path) -> FsResult<PathBuf>;
 }
 
 impl<T: Fs> ReadonlyFs for T {
     fn name(&self) -> String {
-        format!("ReadonlyFs({}", self.name())
+        format!("ReadonlyFs({})", self.name())
     }
-    fn metadata(&self, path: &Path) -> anyhow::Result<Metadata> {
+    fn metadata(&self, path: &Path) -> FsResult<Metadata> {
         self.metadata(path)
     }
 
+    fn list_dir(&self, path: &Path, excluded_dirs: &HashSet<String>) -> FsResult<DirIterator> {
+        self.list_dir(path, excluded_dirs)
+    }
+
     fn exists(&self, path: &Path) -> bool {
         self.exists(path)
     }
+
+    fn sha256(&self, path: &Path) -> FsResult<String> {
+        self.sha256(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> FsResult<String> {
+        self.read_to_string(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> FsResult<PathBuf> {
+        self.canonicalize(path)
+    }
 }
 
 #[derive(Default)]
@@ -44,22 +99,191 @@ impl Fs for StdFs {
         "StdFs".to_string()
     }
 
-    fn create_dir_all(&self, path: &Path) -> anyhow::Result<()> {
+    fn create_dir_all(&self, path: &Path) -> FsResult<()> {
         std::fs::create_dir_all(path)?;
         Ok(())
     }
 
-    fn metadata(&self, path: &Path) -> anyhow::Result<Metadata> {
-        Ok(std::fs::metadata(path)?.into())
+    fn metadata(&self, path: &Path) -> FsResult<Metadata> {
+        let mut metadata: Metadata = std::fs::metadata(path)?.into();
+        // same as in list_dir, symlinks are followed but still marked as symlinks
+        if path.is_symlink() {
+            metadata.mark_symlink();
+        }
+        Ok(metadata)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> FsResult<u64> {
+        Ok(atomic_copy(from, to)?)
+    }
+
+    fn copy_with_sha256(&self, from: &Path, to: &Path) -> FsResult<(u64, String)> {
+        Ok(atomic_copy_with_sha256(from, to)?)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()> {
+        std::fs::rename(from, to)?;
+        Ok(())
+    }
+
+    fn move_file(&self, from: &Path, to: &Path) -> FsResult<()> {
+        Ok(move_with_fallback(from, to, std::fs::rename)?)
     }
 
-    fn copy(&self, from: &Path, to: &Path) -> anyhow::Result<u64> {
-        Ok(std::fs::copy(from, to)?)
+    fn symlink(&self, original: &Path, link: &Path) -> FsResult<()> {
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(original, link)?;
+        #[cfg(windows)]
+        std::os::windows::fs::symlink_file(original, link)?;
+        Ok(())
+    }
+
+    fn replace(&self, from: &Path, to: &Path) -> FsResult<()> {
+        std::fs::rename(from, to)?;
+        Ok(())
+    }
+
+    fn write_bytes(&self, path: &Path, data: &[u8]) -> FsResult<()> {
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &Path, excluded_dirs: &HashSet<String>) -> FsResult<DirIterator> {
+        let excluded_dirs = excluded_dirs.clone();
+        let entries = walkdir::WalkDir::new(path)
+            .into_iter()
+            .filter_entry(move |entry| {
+                entry.depth() == 0
+                    || !entry.file_type().is_dir()
+                    || !excluded_dirs.contains(entry.file_name().to_string_lossy().as_ref())
+            })
+            .map(|entry| {
+                let entry = entry?;
+                // symlinks are followed to keep the target's metadata, but still marked as symlinks
+                let metadata = if entry.path_is_symlink() {
+                    let mut metadata: Metadata = std::fs::metadata(entry.path())
+                        .or_else(|_| std::fs::symlink_metadata(entry.path()))?
+                        .into();
+                    metadata.mark_symlink();
+                    metadata
+                } else {
+                    entry.metadata()?.into()
+                };
+                Ok(DirEntry {
+                    path: entry.into_path(),
+                    metadata,
+                })
+            });
+        Ok(Box::new(entries))
     }
 
     fn exists(&self, path: &Path) -> bool {
         path.exists()
     }
+
+    fn sha256(&self, path: &Path) -> FsResult<String> {
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    }
+
+    fn read_to_string(&self, path: &Path) -> FsResult<String> {
+        Ok(std::fs::read_to_string(path)?)
+    }
+
+    fn canonicalize(&self, path: &Path) -> FsResult<PathBuf> {
+        Ok(std::fs::canonicalize(path)?)
+    }
+}
+
+/// Checks whether `path` is located in a directory with one of the `excluded_dirs` names below `root`.
+pub(crate) fn is_in_excluded_dir(
+    root: &Path,
+    path: &Path,
+    excluded_dirs: &HashSet<String>,
+) -> bool {
+    if excluded_dirs.is_empty() {
+        return false;
+    }
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.components())
+        .any(|component| excluded_dirs.contains(component.as_os_str().to_string_lossy().as_ref()))
+}
+
+/// Moves the file with `rename`, copying it and removing the source if they are on different devices.
+fn move_with_fallback<'a>(
+    from: &'a Path,
+    to: &'a Path,
+    rename: impl FnOnce(&'a Path, &'a Path) -> io::Result<()>,
+) -> io::Result<()> {
+    match rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            atomic_copy(from, to)?;
+            std::fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Copies the file into a temporary `{to}.tmp` file first and then renames it into place,
+/// so an interrupted copy never leaves a partially written file under the final name.
+pub(crate) fn atomic_copy(from: &Path, to: &Path) -> io::Result<u64> {
+    atomic_write(to, |dest| std::fs::copy(from, dest))
+}
+
+/// Same as [`atomic_copy`], but also hashes the content while copying it.
+pub(crate) fn atomic_copy_with_sha256(from: &Path, to: &Path) -> io::Result<(u64, String)> {
+    let mut sha256 = String::new();
+    let size = atomic_write(to, |dest| {
+        let mut reader = HashingReader {
+            inner: File::open(from)?,
+            hasher: Sha256::new(),
+        };
+        let mut writer = File::create(dest)?;
+        let size = io::copy(&mut reader, &mut writer)?;
+        writer.set_permissions(reader.inner.metadata()?.permissions())?;
+        sha256 = format!("{:x}", reader.hasher.finalize());
+        Ok(size)
+    })?;
+    Ok((size, sha256))
+}
+
+/// Runs `write` for a temporary `{to}.tmp` file and renames it into place.
+/// If the rename fails, the temporary file is removed and `write` is retried for `to` directly.
+fn atomic_write(to: &Path, mut write: impl FnMut(&Path) -> io::Result<u64>) -> io::Result<u64> {
+    let mut tmp = to.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let size = match write(&tmp) {
+        Ok(size) => size,
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e);
+        }
+    };
+
+    if std::fs::rename(&tmp, to).is_err() {
+        let _ = std::fs::remove_file(&tmp);
+        return write(to);
+    }
+    Ok(size)
+}
+
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
 }
 
 pub(crate) struct ErrorContextFs<T>(T);
@@ -74,29 +298,286 @@ impl<T: Fs> Fs for ErrorContextFs<T> {
     fn name(&self) -> String {
         format!("ErrorContextFs({})", self.0.name())
     }
-    fn create_dir_all(&self, path: &Path) -> anyhow::Result<()> {
-        self.0
-            .create_dir_all(path)
-            .with_context(|| format!("Failed to create directory [{}]", path.display()))
+    fn create_dir_all(&self, path: &Path) -> FsResult<()> {
+        self.0.create_dir_all(path).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to create directory [{}]",
+                Fs::name(self),
+                path.display()
+            ))
+        })
     }
 
-    fn metadata(&self, path: &Path) -> anyhow::Result<Metadata> {
-        self.0
-            .metadata(path)
-            .with_context(|| format!("Failed to get metadata of [{}]", path.display()))
+    fn metadata(&self, path: &Path) -> FsResult<Metadata> {
+        self.0.metadata(path).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to get metadata of [{}]",
+                Fs::name(self),
+                path.display()
+            ))
+        })
     }
 
-    fn copy(&self, from: &Path, to: &Path) -> anyhow::Result<u64> {
-        self.0.copy(from, to).with_context(|| {
-            format!(
-                "Failed to copy from [{}] to [{}]",
+    fn copy(&self, from: &Path, to: &Path) -> FsResult<u64> {
+        self.0.copy(from, to).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to copy from [{}] to [{}]",
+                Fs::name(self),
                 from.display(),
                 to.display()
-            )
+            ))
         })
     }
 
+    fn copy_with_sha256(&self, from: &Path, to: &Path) -> FsResult<(u64, String)> {
+        self.0.copy_with_sha256(from, to).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to copy from [{}] to [{}]",
+                Fs::name(self),
+                from.display(),
+                to.display()
+            ))
+        })
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.0.rename(from, to).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to rename [{}] to [{}]",
+                Fs::name(self),
+                from.display(),
+                to.display()
+            ))
+        })
+    }
+
+    fn move_file(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.0.move_file(from, to).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to move [{}] to [{}]",
+                Fs::name(self),
+                from.display(),
+                to.display()
+            ))
+        })
+    }
+
+    fn symlink(&self, original: &Path, link: &Path) -> FsResult<()> {
+        self.0.symlink(original, link).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to create symlink [{}] to [{}]",
+                Fs::name(self),
+                link.display(),
+                original.display()
+            ))
+        })
+    }
+
+    fn replace(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.0.replace(from, to).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to replace [{}] with [{}]",
+                Fs::name(self),
+                to.display(),
+                from.display()
+            ))
+        })
+    }
+
+    fn write_bytes(&self, path: &Path, data: &[u8]) -> FsResult<()> {
+        self.0.write_bytes(path, data).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to write [{}]",
+                Fs::name(self),
+                path.display()
+            ))
+        })
+    }
+
+    fn list_dir(&self, path: &Path, excluded_dirs: &HashSet<String>) -> FsResult<DirIterator> {
+        let name = Fs::name(self);
+        let entries = self.0.list_dir(path, excluded_dirs).map_err(|e| {
+            e.context(format!(
+                "[{name}] Failed to list directory [{}]",
+                path.display()
+            ))
+        })?;
+        let path = path.to_path_buf();
+        Ok(Box::new(entries.map(move |entry| {
+            entry.map_err(|e| {
+                e.context(format!(
+                    "[{name}] Failed to list directory [{}]",
+                    path.display()
+                ))
+            })
+        })))
+    }
+
     fn exists(&self, path: &Path) -> bool {
         self.0.exists(path)
     }
+
+    fn sha256(&self, path: &Path) -> FsResult<String> {
+        self.0.sha256(path).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to hash [{}]",
+                Fs::name(self),
+                path.display()
+            ))
+        })
+    }
+
+    fn read_to_string(&self, path: &Path) -> FsResult<String> {
+        self.0.read_to_string(path).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to read [{}]",
+                Fs::name(self),
+                path.display()
+            ))
+        })
+    }
+
+    fn canonicalize(&self, path: &Path) -> FsResult<PathBuf> {
+        self.0.canonicalize(path).map_err(|e| {
+            e.context(format!(
+                "[{}] Failed to resolve [{}]",
+                Fs::name(self),
+                path.display()
+            ))
+        })
+    }
+}
+
+/// Helpers for inspecting a stack of nested [`Fs`] wrappers.
+pub(crate) struct FsStack;
+
+impl FsStack {
+    /// Turns a nested name like `StatFs(ErrorContextFs(StdFs))` into `StatFs > ErrorContextFs > StdFs`.
+    pub(crate) fn describe<T: Fs + ?Sized>(fs: &T) -> String {
+        fs.name()
+            .split('(')
+            .map(|layer| layer.trim_end_matches(')'))
+            .collect::<Vec<_>>()
+            .join(" > ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_source(dir: &Path) -> PathBuf {
+        let source = dir.join("a.jpg");
+        std::fs::write(&source, b"data").unwrap();
+        source
+    }
+
+    fn dir_entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn move_on_the_same_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = write_source(dir.path());
+        let target = dir.path().join("b.jpg");
+
+        move_with_fallback(&source, &target, std::fs::rename).unwrap();
+        assert_eq!(dir_entries(dir.path()), ["b.jpg"]);
+        assert_eq!(std::fs::read(&target).unwrap(), b"data");
+    }
+
+    #[test]
+    fn move_across_devices_copies_and_removes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = write_source(dir.path());
+        let target = dir.path().join("b.jpg");
+
+        move_with_fallback(&source, &target, |_, _| {
+            Err(io::ErrorKind::CrossesDevices.into())
+        })
+        .unwrap();
+        assert_eq!(dir_entries(dir.path()), ["b.jpg"]);
+        assert_eq!(std::fs::read(&target).unwrap(), b"data");
+    }
+
+    #[test]
+    fn move_keeps_the_source_on_other_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = write_source(dir.path());
+        let target = dir.path().join("b.jpg");
+
+        let err = move_with_fallback(&source, &target, |_, _| {
+            Err(io::ErrorKind::PermissionDenied.into())
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(dir_entries(dir.path()), ["a.jpg"]);
+    }
+
+    #[test]
+    fn atomic_copy_leaves_only_the_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = write_source(dir.path());
+        let target = dir.path().join("b.jpg");
+
+        assert_eq!(atomic_copy(&source, &target).unwrap(), 4);
+        assert_eq!(dir_entries(dir.path()), ["a.jpg", "b.jpg"]);
+        assert_eq!(std::fs::read(&target).unwrap(), b"data");
+    }
+
+    #[test]
+    fn atomic_write_removes_the_tmp_file_on_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("b.jpg");
+
+        let err = atomic_write(&target, |dest| {
+            std::fs::write(dest, b"partial")?;
+            Err(io::Error::other("interrupted"))
+        })
+        .unwrap_err();
+        assert_eq!(err.to_string(), "interrupted");
+        assert!(dir_entries(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn atomic_copy_of_a_missing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = atomic_copy(&dir.path().join("a.jpg"), &dir.path().join("b.jpg")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(dir_entries(dir.path()).is_empty());
+    }
+
+    #[test]
+    fn describe_the_fs_stack() {
+        let fs = stat::LocalStatFs::new(
+            ErrorContextFs::new(StdFs),
+            std::rc::Rc::new(stat::Stats::default()),
+        );
+        assert_eq!(FsStack::describe(&fs), "StatFs > ErrorContextFs > StdFs");
+    }
+
+    #[test]
+    fn errors_name_the_fs() {
+        let dir = tempfile::tempdir().unwrap();
+        let fs = ErrorContextFs::new(StdFs);
+        let from = dir.path().join("a.jpg");
+        let to = dir.path().join("b.jpg");
+
+        let err = fs.copy(&from, &to).unwrap_err();
+        assert!(matches!(err, FsError::NotFound(_)));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "[ErrorContextFs(StdFs)] Failed to copy from [{}] to [{}]",
+                from.display(),
+                to.display()
+            )
+        );
+    }
 }
@@ -1,9 +1,11 @@
-use crate::fs::{Fs, Metadata};
-use anyhow::Context;
+use crate::fs::{DirIterator, Fs, FsError, FsResult, Metadata};
+use anyhow::{bail, Context};
 use reflink_copy::ReflinkSupport;
-use std::path::Path;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::atomic::{AtomicU64, AtomicU8};
+use tracing::{debug, warn};
 
 #[derive(Debug, Copy, Clone)]
 enum ReflinkState {
@@ -22,13 +24,33 @@ pub(crate) struct CowFs<T> {
 }
 
 impl<T> CowFs<T> {
-    pub(crate) fn new(fs: T, support: ReflinkSupport) -> Self {
-        assert_ne!(
-            support,
-            ReflinkSupport::NotSupported,
-            "cannot create CowFs for Unsupported fs"
-        );
+    /// Creates `CowFs` if reflink may be supported between `source` and `target`.
+    pub(crate) fn auto(fs: T, source: &Path, target: &Path) -> anyhow::Result<Self> {
+        let support = reflink_copy::check_reflink_support(source, target).with_context(|| {
+            format!(
+                "Failed to check reflink support for [{}] and [{}]",
+                source.display(),
+                target.display()
+            )
+        })?;
+        debug!("reflink support: {:?}", support);
+        if support == ReflinkSupport::NotSupported {
+            bail!(
+                "Reflink is not supported between [{}] and [{}]",
+                source.display(),
+                target.display()
+            );
+        }
+        Ok(Self::new(fs, support))
+    }
 
+    /// Creates `CowFs` if reflink may be supported within `path`.
+    #[allow(dead_code)]
+    pub(crate) fn try_new(fs: T, path: &Path) -> anyhow::Result<Self> {
+        Self::auto(fs, path, path)
+    }
+
+    fn new(fs: T, support: ReflinkSupport) -> Self {
         let reflink_state = match support {
             ReflinkSupport::Supported => ReflinkState::ForceReflink,
             ReflinkSupport::NotSupported => {
@@ -50,21 +72,22 @@ impl<T: Fs> Fs for CowFs<T> {
     fn name(&self) -> String {
         format!("CoW({})", self.fs.name())
     }
-    fn create_dir_all(&self, path: &Path) -> anyhow::Result<()> {
+    fn create_dir_all(&self, path: &Path) -> FsResult<()> {
         self.fs.create_dir_all(path)
     }
 
-    fn metadata(&self, path: &Path) -> anyhow::Result<Metadata> {
+    fn metadata(&self, path: &Path) -> FsResult<Metadata> {
         self.fs.metadata(path)
     }
 
-    fn copy(&self, from: &Path, to: &Path) -> anyhow::Result<u64> {
+    fn copy(&self, from: &Path, to: &Path) -> FsResult<u64> {
         match self.reflink_state.load(Relaxed) {
             0 => reflink_copy::reflink(from, to)
                 .with_context(|| {
                     format!("failed to reflink {} to {}", from.display(), to.display())
                 })
-                .map(|_| 0),
+                .map(|_| 0)
+                .map_err(FsError::from),
             1 => match reflink_copy::reflink_or_copy(from, to)? {
                 None => {
                     self.success_reflinks.fetch_add(1, Relaxed);
@@ -74,7 +97,7 @@ impl<T: Fs> Fs for CowFs<T> {
                     let fails_count = self.failed_reflinks.fetch_add(1, Relaxed);
                     if fails_count > MAX_FAILS_COUNT && self.success_reflinks.load(Relaxed) == 0 {
                         self.reflink_state.store(ReflinkState::Copy as u8, Relaxed);
-                        eprintln!("reflink doesn't work, permanently switching to copy");
+                        warn!("reflink doesn't work, permanently switching to copy");
                     }
                     Ok(size)
                 }
@@ -83,7 +106,99 @@ impl<T: Fs> Fs for CowFs<T> {
         }
     }
 
+    /// Reflinked data never passes through the process, so the copy is hashed afterwards.
+    fn copy_with_sha256(&self, from: &Path, to: &Path) -> FsResult<(u64, String)> {
+        let size = self.copy(from, to)?;
+        Ok((size, self.fs.sha256(to)?))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.fs.rename(from, to)
+    }
+
+    fn move_file(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.fs.move_file(from, to)
+    }
+
+    fn symlink(&self, original: &Path, link: &Path) -> FsResult<()> {
+        self.fs.symlink(original, link)
+    }
+
+    fn replace(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.fs.replace(from, to)
+    }
+
+    fn write_bytes(&self, path: &Path, data: &[u8]) -> FsResult<()> {
+        self.fs.write_bytes(path, data)
+    }
+
+    fn list_dir(&self, path: &Path, excluded_dirs: &HashSet<String>) -> FsResult<DirIterator> {
+        self.fs.list_dir(path, excluded_dirs)
+    }
+
     fn exists(&self, path: &Path) -> bool {
         self.fs.exists(path)
     }
+
+    fn sha256(&self, path: &Path) -> FsResult<String> {
+        self.fs.sha256(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> FsResult<String> {
+        self.fs.read_to_string(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> FsResult<PathBuf> {
+        self.fs.canonicalize(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::StdFs;
+
+    #[test]
+    fn auto_on_a_temp_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let target = dir.path().join("target");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::create_dir(&target).unwrap();
+
+        match CowFs::auto(StdFs, &source, &target) {
+            Ok(fs) => {
+                std::fs::write(source.join("a.jpg"), b"data").unwrap();
+                let size = fs
+                    .copy(&source.join("a.jpg"), &target.join("a.jpg"))
+                    .unwrap();
+                assert_eq!(size, 4);
+                assert_eq!(std::fs::read(target.join("a.jpg")).unwrap(), b"data");
+            }
+            // tmpfs and many other filesystems don't support reflink
+            Err(e) => assert!(
+                e.to_string().contains(&source.display().to_string()),
+                "unexpected error: {e:#}"
+            ),
+        }
+    }
+
+    #[test]
+    fn try_new_on_a_temp_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        match CowFs::try_new(StdFs, dir.path()) {
+            Ok(fs) => {
+                std::fs::write(dir.path().join("a.jpg"), b"data").unwrap();
+                let size = fs
+                    .copy(&dir.path().join("a.jpg"), &dir.path().join("b.jpg"))
+                    .unwrap();
+                assert_eq!(size, 4);
+                assert_eq!(std::fs::read(dir.path().join("b.jpg")).unwrap(), b"data");
+            }
+            Err(e) => assert!(
+                e.to_string().contains(&dir.path().display().to_string()),
+                "unexpected error: {e:#}"
+            ),
+        }
+    }
 }
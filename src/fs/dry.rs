@@ -1,36 +1,177 @@
-use super::Fs;
-use super::{Metadata, ReadonlyFs};
-use anyhow::{bail, Context};
+use super::{
+    is_in_excluded_dir, DirEntry, DirIterator, Fs, FsError, FsResult, Metadata, ReadonlyFs,
+};
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
 use std::cell::{Ref, RefCell};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-pub(crate) type ObjectMap = HashMap<PathBuf, (Metadata, Option<PathBuf>)>;
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ObjectMap(HashMap<PathBuf, (Metadata, Option<PathBuf>)>);
+
+impl ObjectMap {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn to_json(&self) -> anyhow::Result<String> {
+        serde_json::to_string_pretty(self).with_context(|| "Failed to serialize object map")
+    }
+
+    pub(crate) fn from_json(s: &str) -> anyhow::Result<ObjectMap> {
+        serde_json::from_str(s).with_context(|| "Failed to deserialize object map")
+    }
+}
+
+impl Deref for ObjectMap {
+    type Target = HashMap<PathBuf, (Metadata, Option<PathBuf>)>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for ObjectMap {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+/// Difference between two [`ObjectMap`]s, see [`diff_object_maps`].
+#[derive(Debug, PartialEq)]
+pub(crate) enum DiffEntry {
+    /// Exists only in the second map.
+    Added(PathBuf),
+    /// Exists only in the first map.
+    Removed(PathBuf),
+    /// Exists in both maps, but with different metadata or source.
+    Changed(PathBuf),
+}
+
+/// Compares two object maps, returning the differences sorted by path.
+/// Directories are considered equal regardless of their timestamps, as they are created during the run.
+pub(crate) fn diff_object_maps(before: &ObjectMap, after: &ObjectMap) -> Vec<DiffEntry> {
+    let mut paths: Vec<&PathBuf> = before.keys().chain(after.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut diff = Vec::new();
+    for path in paths {
+        match (before.get(path), after.get(path)) {
+            (Some(_), None) => diff.push(DiffEntry::Removed(path.clone())),
+            (None, Some(_)) => diff.push(DiffEntry::Added(path.clone())),
+            (Some((before_meta, before_source)), Some((after_meta, after_source))) => {
+                let same_meta =
+                    before_meta == after_meta || (before_meta.is_dir() && after_meta.is_dir());
+                if !same_meta || before_source != after_source {
+                    diff.push(DiffEntry::Changed(path.clone()));
+                }
+            }
+            (None, None) => unreachable!(),
+        }
+    }
+    diff
+}
 
 pub(crate) struct DryFs<T> {
     fs: T,
-    objects: RefCell<ObjectMap>,
+    objects: Rc<RefCell<ObjectMap>>,
+    /// Files of the underlying fs that have been removed or moved away during the dry run.
+    removed: RefCell<HashSet<PathBuf>>,
 }
 
 impl<T> DryFs<T> {
-    pub(crate) fn new(fs: T, objects: RefCell<ObjectMap>) -> Self {
-        Self { fs, objects }
+    pub(crate) fn new(fs: T, objects: Rc<RefCell<ObjectMap>>) -> Self {
+        Self {
+            fs,
+            objects,
+            removed: RefCell::new(HashSet::new()),
+        }
     }
 
     fn add_object(&self, path: PathBuf, meta: Metadata, source: Option<PathBuf>) {
+        self.removed.borrow_mut().remove(&path);
         self.objects.borrow_mut().insert(path, (meta, source));
     }
 
-    fn find_object(&self, path: &Path) -> Option<Ref<Metadata>> {
+    fn remove_object(&self, path: &Path) -> Option<(Metadata, Option<PathBuf>)> {
+        self.removed.borrow_mut().insert(path.to_path_buf());
+        self.objects.borrow_mut().remove(path)
+    }
+
+    fn is_removed(&self, path: &Path) -> bool {
+        self.removed.borrow().contains(path)
+    }
+
+    fn find_object(&self, path: &Path) -> Option<Ref<'_, Metadata>> {
         let borrow = self.objects.borrow();
         Ref::filter_map(borrow, |objects| objects.get(path).map(|item| &item.0)).ok()
     }
 }
+impl<T: ReadonlyFs> DryFs<T> {
+    fn parent_exists(&self, path: &Path) -> bool {
+        match path.parent() {
+            Some(parent) => self.find_object(parent).is_some() || self.fs.exists(parent),
+            None => false,
+        }
+    }
+
+    /// Checks that an object can be created at `path`, like the real fs would.
+    fn check_new_object(&self, path: &Path) -> FsResult<()> {
+        if Fs::exists(self, path) {
+            return Err(FsError::Other(anyhow!(
+                "Object [{}] already exist",
+                path.display()
+            )));
+        }
+        if !self.parent_exists(path) {
+            return Err(FsError::NotFound(anyhow!(
+                "Parent directory does not exist: {}",
+                path.display()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the real file holding the content of `path`.
+    fn content_source(&self, path: &Path) -> FsResult<PathBuf> {
+        if self.is_removed(path) {
+            return Err(FsError::NotFound(anyhow!(
+                "Object [{}] does not exist",
+                path.display()
+            )));
+        }
+        match self.objects.borrow().get(path) {
+            Some((_, Some(source))) => Ok(source.clone()),
+            Some((_, None)) => Err(FsError::Other(anyhow!(
+                "Object [{}] exists only in memory and has no content",
+                path.display()
+            ))),
+            None => Ok(path.to_path_buf()),
+        }
+    }
+
+    /// Moves an in-memory object, keeping the original source of the file for traceability.
+    fn move_object(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.check_new_object(to)?;
+        let meta = Fs::metadata(self, from)?;
+        let source = self
+            .remove_object(from)
+            .and_then(|(_, source)| source)
+            .unwrap_or_else(|| from.to_path_buf());
+        self.add_object(to.to_path_buf(), meta, Some(source));
+        Ok(())
+    }
+}
+
 impl<T: ReadonlyFs> Fs for DryFs<T> {
     fn name(&self) -> String {
         format!("Dry({})", self.fs.name())
     }
-    fn create_dir_all(&self, path: &Path) -> anyhow::Result<()> {
+    fn create_dir_all(&self, path: &Path) -> FsResult<()> {
         if Fs::exists(self, path) {
             return Ok(());
         }
@@ -43,7 +184,13 @@ impl<T: ReadonlyFs> Fs for DryFs<T> {
         Ok(())
     }
 
-    fn metadata(&self, path: &Path) -> anyhow::Result<Metadata> {
+    fn metadata(&self, path: &Path) -> FsResult<Metadata> {
+        if self.is_removed(path) {
+            return Err(FsError::NotFound(anyhow!(
+                "Object [{}] does not exist",
+                path.display()
+            )));
+        }
         if let Some(metadata) = self.find_object(path) {
             Ok(metadata.clone())
         } else {
@@ -51,17 +198,379 @@ impl<T: ReadonlyFs> Fs for DryFs<T> {
         }
     }
 
-    fn copy(&self, from: &Path, to: &Path) -> anyhow::Result<u64> {
-        if Fs::exists(self, to) {
-            bail!("Object [{}] already exist", to.display());
-        }
+    fn copy(&self, from: &Path, to: &Path) -> FsResult<u64> {
+        self.check_new_object(to)?;
         let meta = Fs::metadata(self, from)?;
         let len = meta.len();
         self.add_object(to.to_path_buf(), meta, Some(from.to_path_buf()));
         Ok(len)
     }
 
+    fn copy_with_sha256(&self, from: &Path, to: &Path) -> FsResult<(u64, String)> {
+        let sha256 = Fs::sha256(self, from)?;
+        Ok((self.copy(from, to)?, sha256))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.move_object(from, to)
+    }
+
+    fn move_file(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.move_object(from, to)
+    }
+
+    fn symlink(&self, original: &Path, link: &Path) -> FsResult<()> {
+        self.check_new_object(link)?;
+        let mut meta = Fs::metadata(self, original)?;
+        meta.mark_symlink();
+        self.add_object(link.to_path_buf(), meta, Some(original.to_path_buf()));
+        Ok(())
+    }
+
+    fn replace(&self, from: &Path, to: &Path) -> FsResult<()> {
+        let meta = Fs::metadata(self, from)?;
+        let source = self
+            .remove_object(from)
+            .and_then(|(_, source)| source)
+            .unwrap_or_else(|| from.to_path_buf());
+        self.add_object(to.to_path_buf(), meta, Some(source));
+        Ok(())
+    }
+
+    fn write_bytes(&self, path: &Path, data: &[u8]) -> FsResult<()> {
+        if !self.parent_exists(path) {
+            return Err(FsError::NotFound(anyhow!(
+                "Parent directory does not exist: {}",
+                path.display()
+            )));
+        }
+        self.add_object(
+            path.to_path_buf(),
+            Metadata::dummy_file(data.len() as u64),
+            None,
+        );
+        Ok(())
+    }
+
+    fn list_dir(&self, path: &Path, excluded_dirs: &HashSet<String>) -> FsResult<DirIterator> {
+        let mut entries = Vec::new();
+        // the path may exist only in memory, e.g. a target directory created during the dry run
+        if self.fs.exists(path) || self.find_object(path).is_none() {
+            for entry in self.fs.list_dir(path, excluded_dirs)? {
+                let entry = entry?;
+                if self.find_object(&entry.path).is_none() && !self.is_removed(&entry.path) {
+                    entries.push(Ok(entry));
+                }
+            }
+        }
+        for (object_path, (metadata, _)) in self.objects.borrow().iter() {
+            if object_path.starts_with(path)
+                && !is_in_excluded_dir(path, object_path, excluded_dirs)
+            {
+                entries.push(Ok(DirEntry {
+                    path: object_path.clone(),
+                    metadata: metadata.clone(),
+                }));
+            }
+        }
+        Ok(Box::new(entries.into_iter()))
+    }
+
     fn exists(&self, path: &Path) -> bool {
-        self.find_object(path).is_some() || self.fs.exists(path)
+        !self.is_removed(path) && (self.find_object(path).is_some() || self.fs.exists(path))
+    }
+
+    /// In-memory files are hashed using the real file they have been created from.
+    fn sha256(&self, path: &Path) -> FsResult<String> {
+        self.fs.sha256(&self.content_source(path)?)
+    }
+
+    /// In-memory files are read from the real file they have been created from.
+    fn read_to_string(&self, path: &Path) -> FsResult<String> {
+        self.fs.read_to_string(&self.content_source(path)?)
+    }
+
+    /// In-memory symlinks resolve to their original, other in-memory objects to themselves.
+    fn canonicalize(&self, path: &Path) -> FsResult<PathBuf> {
+        Fs::metadata(self, path)?;
+        match self.objects.borrow().get(path) {
+            Some((meta, Some(original))) if meta.is_symlink() => Ok(original.clone()),
+            Some(_) => Ok(path.to_path_buf()),
+            None => self.fs.canonicalize(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::StdFs;
+    use crate::test_util::{date, EmptyFs};
+
+    fn file(len: u64, modified: &str) -> Metadata {
+        Metadata::dummy_file(len).with_modified(date(modified))
+    }
+
+    #[test]
+    fn move_keeps_the_original_source() {
+        let objects = Rc::new(RefCell::new(ObjectMap::new()));
+        let fs = DryFs::new(EmptyFs, Rc::clone(&objects));
+        fs.create_dir_all(Path::new("/src")).unwrap();
+        fs.create_dir_all(Path::new("/dst")).unwrap();
+        objects.borrow_mut().insert(
+            PathBuf::from("/src/a.jpg"),
+            (Metadata::dummy_file(10), None),
+        );
+
+        fs.move_file(Path::new("/src/a.jpg"), Path::new("/dst/a.jpg"))
+            .unwrap();
+        fs.move_file(Path::new("/dst/a.jpg"), Path::new("/dst/b.jpg"))
+            .unwrap();
+
+        assert!(!Fs::exists(&fs, Path::new("/src/a.jpg")));
+        assert!(!Fs::exists(&fs, Path::new("/dst/a.jpg")));
+        let (meta, source) = objects.borrow()[Path::new("/dst/b.jpg")].clone();
+        assert_eq!(meta.len(), 10);
+        assert_eq!(source, Some(PathBuf::from("/src/a.jpg")));
+    }
+
+    #[test]
+    fn object_map_json_round_trip() {
+        let mut objects = ObjectMap::new();
+        objects.insert(
+            PathBuf::from("/dst/2024/01/01/2024-01-01T000000.jpg"),
+            (
+                file(10, "2024-01-01T00:00:00Z"),
+                Some(PathBuf::from("/src/a.jpg")),
+            ),
+        );
+        objects.insert(
+            PathBuf::from("/dst"),
+            (
+                Metadata::dummy_folder().with_modified(date("2024-02-01T00:00:00Z")),
+                None,
+            ),
+        );
+
+        let json = objects.to_json().unwrap();
+        assert!(json.contains(r#""modified": 1704067200"#), "{json}");
+        assert_eq!(ObjectMap::from_json(&json).unwrap(), objects);
+    }
+
+    #[test]
+    fn diff_of_object_maps() {
+        let mut before = ObjectMap::new();
+        before.insert(PathBuf::from("/dst"), (Metadata::dummy_folder(), None));
+        before.insert(
+            PathBuf::from("/dst/a.jpg"),
+            (file(10, "2024-01-01T00:00:00Z"), None),
+        );
+        before.insert(
+            PathBuf::from("/dst/b.jpg"),
+            (file(10, "2024-01-01T00:00:00Z"), None),
+        );
+        before.insert(
+            PathBuf::from("/dst/c.jpg"),
+            (file(10, "2024-01-01T00:00:00Z"), None),
+        );
+        let mut after = ObjectMap::new();
+        after.insert(
+            PathBuf::from("/dst"),
+            (
+                Metadata::dummy_folder().with_modified(date("2024-02-01T00:00:00Z")),
+                None,
+            ),
+        );
+        after.insert(
+            PathBuf::from("/dst/a.jpg"),
+            (file(10, "2024-01-01T00:00:00Z"), None),
+        );
+        after.insert(
+            PathBuf::from("/dst/b.jpg"),
+            (file(20, "2024-01-01T00:00:00Z"), None),
+        );
+        after.insert(
+            PathBuf::from("/dst/d.jpg"),
+            (file(10, "2024-01-01T00:00:00Z"), None),
+        );
+
+        assert_eq!(
+            diff_object_maps(&before, &after),
+            [
+                DiffEntry::Changed(PathBuf::from("/dst/b.jpg")),
+                DiffEntry::Removed(PathBuf::from("/dst/c.jpg")),
+                DiffEntry::Added(PathBuf::from("/dst/d.jpg")),
+            ]
+        );
+    }
+
+    fn sorted_entries(fs: &dyn Fs, path: &Path) -> Vec<(PathBuf, bool)> {
+        let mut entries: Vec<(PathBuf, bool)> = fs
+            .list_dir(path, &HashSet::new())
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                (entry.path, entry.metadata.is_dir())
+            })
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn list_dir_merges_real_and_in_memory_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(root.join("a.jpg"), b"a").unwrap();
+        std::fs::write(root.join("sub/b.jpg"), b"b").unwrap();
+        assert_eq!(
+            sorted_entries(&StdFs, root),
+            [
+                (root.to_path_buf(), true),
+                (root.join("a.jpg"), false),
+                (root.join("sub"), true),
+                (root.join("sub/b.jpg"), false),
+            ]
+        );
+
+        let fs = DryFs::new(StdFs, Rc::new(RefCell::new(ObjectMap::new())));
+        fs.create_dir_all(&root.join("new")).unwrap();
+        fs.copy(&root.join("a.jpg"), &root.join("new/c.jpg"))
+            .unwrap();
+        fs.move_file(&root.join("sub/b.jpg"), &root.join("d.jpg"))
+            .unwrap();
+
+        assert_eq!(
+            sorted_entries(&fs, root),
+            [
+                (root.to_path_buf(), true),
+                (root.join("a.jpg"), false),
+                (root.join("d.jpg"), false),
+                (root.join("new"), true),
+                (root.join("new/c.jpg"), false),
+                (root.join("sub"), true),
+            ]
+        );
+        assert_eq!(
+            sorted_entries(&fs, &root.join("new")),
+            [(root.join("new"), true), (root.join("new/c.jpg"), false)]
+        );
+        // nothing has been written to the disk
+        assert!(!root.join("new").exists());
+        assert!(root.join("sub/b.jpg").exists());
+    }
+
+    #[test]
+    fn copy_requires_the_parent_directory() {
+        let objects = Rc::new(RefCell::new(ObjectMap::new()));
+        let fs = DryFs::new(EmptyFs, Rc::clone(&objects));
+        fs.create_dir_all(Path::new("/src")).unwrap();
+        objects.borrow_mut().insert(
+            PathBuf::from("/src/a.jpg"),
+            (Metadata::dummy_file(10), None),
+        );
+
+        let err = fs
+            .copy(Path::new("/src/a.jpg"), Path::new("/dst/a.jpg"))
+            .unwrap_err();
+        assert!(matches!(err, FsError::NotFound(_)));
+        assert_eq!(
+            err.to_string(),
+            "Parent directory does not exist: /dst/a.jpg"
+        );
+        assert!(!Fs::exists(&fs, Path::new("/dst/a.jpg")));
+
+        fs.create_dir_all(Path::new("/dst")).unwrap();
+        assert_eq!(
+            fs.copy(Path::new("/src/a.jpg"), Path::new("/dst/a.jpg"))
+                .unwrap(),
+            10
+        );
+    }
+
+    #[test]
+    fn write_bytes_adds_an_object_without_source() {
+        let objects = Rc::new(RefCell::new(ObjectMap::new()));
+        let fs = DryFs::new(EmptyFs, Rc::clone(&objects));
+        fs.create_dir_all(Path::new("/dst")).unwrap();
+
+        fs.write_bytes(Path::new("/dst/SHA256SUMS"), b"checksums")
+            .unwrap();
+
+        let (meta, source) = objects.borrow()[Path::new("/dst/SHA256SUMS")].clone();
+        assert!(meta.is_file());
+        assert_eq!(meta.len(), 9);
+        assert_eq!(source, None);
+    }
+
+    #[test]
+    fn read_to_string_uses_the_real_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("SHA256SUMS"), b"abc  a.jpg\n").unwrap();
+        let fs = DryFs::new(StdFs, Rc::new(RefCell::new(ObjectMap::new())));
+
+        fs.copy(&root.join("SHA256SUMS"), &root.join("copy"))
+            .unwrap();
+        assert_eq!(
+            Fs::read_to_string(&fs, &root.join("copy")).unwrap(),
+            "abc  a.jpg\n"
+        );
+
+        fs.write_bytes(&root.join("SHA256SUMS"), b"def  b.jpg\n")
+            .unwrap();
+        let err = Fs::read_to_string(&fs, &root.join("SHA256SUMS")).unwrap_err();
+        assert!(matches!(err, FsError::Other(_)));
+        // the real file is left untouched
+        assert_eq!(
+            std::fs::read_to_string(root.join("SHA256SUMS")).unwrap(),
+            "abc  a.jpg\n"
+        );
+    }
+
+    #[test]
+    fn symlink_is_marked_and_points_to_the_original() {
+        let objects = Rc::new(RefCell::new(ObjectMap::new()));
+        let fs = DryFs::new(EmptyFs, Rc::clone(&objects));
+        fs.create_dir_all(Path::new("/src")).unwrap();
+        fs.create_dir_all(Path::new("/dst")).unwrap();
+        objects.borrow_mut().insert(
+            PathBuf::from("/src/a.jpg"),
+            (Metadata::dummy_file(10), None),
+        );
+
+        fs.symlink(Path::new("/src/a.jpg"), Path::new("/dst/a.jpg"))
+            .unwrap();
+
+        let (meta, source) = objects.borrow()[Path::new("/dst/a.jpg")].clone();
+        assert!(meta.is_symlink());
+        assert_eq!(meta.len(), 10);
+        assert_eq!(source, Some(PathBuf::from("/src/a.jpg")));
+        assert!(Fs::exists(&fs, Path::new("/src/a.jpg")));
+    }
+
+    #[test]
+    fn canonicalize_resolves_in_memory_symlinks() {
+        let objects = Rc::new(RefCell::new(ObjectMap::new()));
+        let fs = DryFs::new(EmptyFs, Rc::clone(&objects));
+        fs.create_dir_all(Path::new("/src")).unwrap();
+        objects.borrow_mut().insert(
+            PathBuf::from("/src/a.jpg"),
+            (Metadata::dummy_file(10), None),
+        );
+        fs.symlink(Path::new("/src/a.jpg"), Path::new("/src/link.jpg"))
+            .unwrap();
+
+        assert_eq!(
+            Fs::canonicalize(&fs, Path::new("/src/link.jpg")).unwrap(),
+            PathBuf::from("/src/a.jpg")
+        );
+        assert_eq!(
+            Fs::canonicalize(&fs, Path::new("/src/a.jpg")).unwrap(),
+            PathBuf::from("/src/a.jpg")
+        );
+        let err = Fs::canonicalize(&fs, Path::new("/src/missing.jpg")).unwrap_err();
+        assert!(matches!(err, FsError::NotFound(_)));
     }
 }
@@ -0,0 +1,150 @@
+use std::fmt;
+use std::io;
+
+pub(crate) type FsResult<T> = Result<T, FsError>;
+
+/// Error returned by [`Fs`](super::Fs) operations.
+/// Every variant keeps the underlying error with its context, the variant only tells what kind of failure it was.
+#[derive(Debug)]
+pub(crate) enum FsError {
+    NotFound(anyhow::Error),
+    PermissionDenied(anyhow::Error),
+    DiskFull(anyhow::Error),
+    CrossDevice(anyhow::Error),
+    Other(anyhow::Error),
+}
+
+impl FsError {
+    fn from_kind(kind: io::ErrorKind) -> fn(anyhow::Error) -> Self {
+        match kind {
+            io::ErrorKind::NotFound => Self::NotFound,
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+            io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded => Self::DiskFull,
+            io::ErrorKind::CrossesDevices => Self::CrossDevice,
+            _ => Self::Other,
+        }
+    }
+
+    pub(crate) fn inner(&self) -> &anyhow::Error {
+        match self {
+            Self::NotFound(e)
+            | Self::PermissionDenied(e)
+            | Self::DiskFull(e)
+            | Self::CrossDevice(e)
+            | Self::Other(e) => e,
+        }
+    }
+
+    /// Adds context to the underlying error, keeping the kind.
+    pub(crate) fn context<C>(self, context: C) -> Self
+    where
+        C: fmt::Display + Send + Sync + 'static,
+    {
+        match self {
+            Self::NotFound(e) => Self::NotFound(e.context(context)),
+            Self::PermissionDenied(e) => Self::PermissionDenied(e.context(context)),
+            Self::DiskFull(e) => Self::DiskFull(e.context(context)),
+            Self::CrossDevice(e) => Self::CrossDevice(e.context(context)),
+            Self::Other(e) => Self::Other(e.context(context)),
+        }
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.inner(), f)
+    }
+}
+
+impl std::error::Error for FsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner().source()
+    }
+}
+
+impl From<io::Error> for FsError {
+    fn from(e: io::Error) -> Self {
+        Self::from_kind(e.kind())(e.into())
+    }
+}
+
+impl From<walkdir::Error> for FsError {
+    fn from(e: walkdir::Error) -> Self {
+        anyhow::Error::from(e).into()
+    }
+}
+
+/// Classifies the error by the first [`io::Error`] in its chain.
+impl From<anyhow::Error> for FsError {
+    fn from(e: anyhow::Error) -> Self {
+        let kind = e
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+            .map(io::Error::kind);
+        match kind {
+            Some(kind) => Self::from_kind(kind)(e),
+            None => Self::Other(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn io_errors_are_classified_by_kind() {
+        let kind = |kind: io::ErrorKind| FsError::from(io::Error::from(kind));
+        assert!(matches!(
+            kind(io::ErrorKind::NotFound),
+            FsError::NotFound(_)
+        ));
+        assert!(matches!(
+            kind(io::ErrorKind::PermissionDenied),
+            FsError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            kind(io::ErrorKind::StorageFull),
+            FsError::DiskFull(_)
+        ));
+        assert!(matches!(
+            kind(io::ErrorKind::QuotaExceeded),
+            FsError::DiskFull(_)
+        ));
+        assert!(matches!(
+            kind(io::ErrorKind::CrossesDevices),
+            FsError::CrossDevice(_)
+        ));
+        assert!(matches!(
+            kind(io::ErrorKind::Interrupted),
+            FsError::Other(_)
+        ));
+    }
+
+    #[test]
+    fn context_keeps_the_kind() {
+        let e =
+            FsError::from(io::Error::from(io::ErrorKind::StorageFull)).context("Failed to copy");
+        assert!(matches!(e, FsError::DiskFull(_)));
+        assert_eq!(e.to_string(), "Failed to copy");
+        assert!(e
+            .inner()
+            .root_cause()
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::StorageFull));
+    }
+
+    #[test]
+    fn anyhow_errors_are_classified_by_the_io_error_in_the_chain() {
+        let e = Err::<(), _>(io::Error::from(io::ErrorKind::PermissionDenied))
+            .context("Failed to open")
+            .context("Failed to process")
+            .unwrap_err();
+        assert!(matches!(FsError::from(e), FsError::PermissionDenied(_)));
+        assert!(matches!(
+            FsError::from(anyhow::anyhow!("no io error")),
+            FsError::Other(_)
+        ));
+    }
+}
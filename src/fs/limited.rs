@@ -0,0 +1,188 @@
+use crate::fs::{DirIterator, Fs, FsError, FsResult, Metadata};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+#[derive(Debug, Copy, Clone)]
+pub(crate) enum LimitExceeded {
+    Files(u64),
+    Bytes(u64),
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitExceeded::Files(max) => write!(f, "limit of {max} copied files has been reached"),
+            LimitExceeded::Bytes(max) => write!(f, "limit of {max} copied bytes has been reached"),
+        }
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+/// Stops copying once the configured number of files or bytes has been copied.
+pub(crate) struct LimitedFs<T> {
+    fs: T,
+    max_files: Option<u64>,
+    max_bytes: Option<u64>,
+    copied_files: AtomicU64,
+    copied_bytes: AtomicU64,
+}
+
+impl<T> LimitedFs<T> {
+    pub(crate) fn new(fs: T, max_files: Option<u64>, max_bytes: Option<u64>) -> Self {
+        Self {
+            fs,
+            max_files,
+            max_bytes,
+            copied_files: AtomicU64::new(0),
+            copied_bytes: AtomicU64::new(0),
+        }
+    }
+
+    fn reserve(counter: &AtomicU64, amount: u64, max: Option<u64>) -> bool {
+        match max {
+            None => {
+                counter.fetch_add(amount, Relaxed);
+                true
+            }
+            Some(max) => counter
+                .fetch_update(Relaxed, Relaxed, |used| {
+                    used.checked_add(amount).filter(|total| *total <= max)
+                })
+                .is_ok(),
+        }
+    }
+
+    /// Reserves one file and `len` bytes before running `transfer`, releasing them if it fails.
+    fn limited<R>(&self, len: u64, transfer: impl FnOnce() -> FsResult<R>) -> FsResult<R> {
+        if !Self::reserve(&self.copied_files, 1, self.max_files) {
+            return Err(FsError::Other(
+                LimitExceeded::Files(self.max_files.unwrap_or_default()).into(),
+            ));
+        }
+        if !Self::reserve(&self.copied_bytes, len, self.max_bytes) {
+            self.copied_files.fetch_sub(1, Relaxed);
+            return Err(FsError::Other(
+                LimitExceeded::Bytes(self.max_bytes.unwrap_or_default()).into(),
+            ));
+        }
+
+        let result = transfer();
+        if result.is_err() {
+            self.copied_files.fetch_sub(1, Relaxed);
+            self.copied_bytes.fetch_sub(len, Relaxed);
+        }
+        result
+    }
+}
+
+impl<T: Fs> Fs for LimitedFs<T> {
+    fn name(&self) -> String {
+        format!("LimitedFs({})", self.fs.name())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> FsResult<()> {
+        self.fs.create_dir_all(path)
+    }
+
+    fn metadata(&self, path: &Path) -> FsResult<Metadata> {
+        self.fs.metadata(path)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> FsResult<u64> {
+        let len = self.fs.metadata(from)?.len();
+        self.limited(len, || self.fs.copy(from, to))
+    }
+
+    fn copy_with_sha256(&self, from: &Path, to: &Path) -> FsResult<(u64, String)> {
+        let len = self.fs.metadata(from)?.len();
+        self.limited(len, || self.fs.copy_with_sha256(from, to))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.fs.rename(from, to)
+    }
+
+    fn move_file(&self, from: &Path, to: &Path) -> FsResult<()> {
+        let len = self.fs.metadata(from)?.len();
+        self.limited(len, || self.fs.move_file(from, to))
+    }
+
+    fn symlink(&self, original: &Path, link: &Path) -> FsResult<()> {
+        self.fs.symlink(original, link)
+    }
+
+    fn replace(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.fs.replace(from, to)
+    }
+
+    fn write_bytes(&self, path: &Path, data: &[u8]) -> FsResult<()> {
+        self.fs.write_bytes(path, data)
+    }
+
+    fn list_dir(&self, path: &Path, excluded_dirs: &HashSet<String>) -> FsResult<DirIterator> {
+        self.fs.list_dir(path, excluded_dirs)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.fs.exists(path)
+    }
+
+    fn sha256(&self, path: &Path) -> FsResult<String> {
+        self.fs.sha256(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> FsResult<String> {
+        self.fs.read_to_string(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> FsResult<PathBuf> {
+        self.fs.canonicalize(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::StdFs;
+
+    fn limit_exceeded(result: FsResult<u64>) -> LimitExceeded {
+        match result {
+            Err(FsError::Other(e)) => *e.downcast_ref::<LimitExceeded>().unwrap(),
+            other => panic!("unexpected result: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn max_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("a.jpg");
+        std::fs::write(&source, b"data").unwrap();
+        let fs = LimitedFs::new(StdFs, Some(2), None);
+
+        fs.copy(&source, &dir.path().join("b.jpg")).unwrap();
+        // failed copies don't count
+        fs.copy(&dir.path().join("missing.jpg"), &dir.path().join("c.jpg"))
+            .unwrap_err();
+        fs.copy(&source, &dir.path().join("c.jpg")).unwrap();
+        let result = fs.copy(&source, &dir.path().join("d.jpg"));
+        assert!(matches!(limit_exceeded(result), LimitExceeded::Files(2)));
+        assert!(!dir.path().join("d.jpg").exists());
+    }
+
+    #[test]
+    fn max_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("a.jpg");
+        std::fs::write(&source, b"data").unwrap();
+        let fs = LimitedFs::new(StdFs, None, Some(10));
+
+        fs.copy(&source, &dir.path().join("b.jpg")).unwrap();
+        fs.copy(&source, &dir.path().join("c.jpg")).unwrap();
+        let result = fs.copy(&source, &dir.path().join("d.jpg"));
+        assert!(matches!(limit_exceeded(result), LimitExceeded::Bytes(10)));
+    }
+}
@@ -1,8 +1,9 @@
 use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 bitflags! {
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
     struct FileFlags: u8 {
         const NONE = 0b000;
         const IS_DIR = 0b001;
@@ -11,10 +12,13 @@ bitflags! {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Metadata {
     len: u64,
+    #[serde(with = "unix_seconds")]
     modified: SystemTime,
+    #[serde(default, with = "unix_seconds::option")]
+    created: Option<SystemTime>,
     flags: FileFlags,
 }
 
@@ -23,14 +27,42 @@ impl Metadata {
         Self {
             len: 0,
             modified: SystemTime::now(),
+            created: None,
             flags: FileFlags::IS_DIR,
         }
     }
 
+    pub fn dummy_file(len: u64) -> Self {
+        Self {
+            len,
+            modified: SystemTime::now(),
+            created: None,
+            flags: FileFlags::IS_FILE,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_modified(mut self, modified: SystemTime) -> Self {
+        self.modified = modified;
+        self
+    }
+
     pub fn is_dir(&self) -> bool {
         self.flags.contains(FileFlags::IS_DIR)
     }
 
+    pub fn is_file(&self) -> bool {
+        self.flags.contains(FileFlags::IS_FILE)
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.flags.contains(FileFlags::IS_SYMLINK)
+    }
+
+    pub fn mark_symlink(&mut self) {
+        self.flags.insert(FileFlags::IS_SYMLINK);
+    }
+
     pub fn len(&self) -> u64 {
         self.len
     }
@@ -38,6 +70,11 @@ impl Metadata {
     pub fn modified(&self) -> SystemTime {
         self.modified
     }
+
+    /// Birth time of the file, if the platform and filesystem support it.
+    pub fn created(&self) -> Option<SystemTime> {
+        self.created
+    }
 }
 
 impl From<std::fs::Metadata> for Metadata {
@@ -58,7 +95,49 @@ impl From<std::fs::Metadata> for Metadata {
         Self {
             len: metadata.len(),
             modified,
+            created: metadata.created().ok(),
             flags,
         }
     }
 }
+
+/// Serializes `SystemTime` as the number of seconds since the Unix epoch.
+mod unix_seconds {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let seconds = time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        seconds.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let seconds = u64::deserialize(deserializer)?;
+        Ok(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use std::time::SystemTime;
+
+        pub fn serialize<S: Serializer>(
+            time: &Option<SystemTime>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match time {
+                Some(time) => super::serialize(time, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<SystemTime>, D::Error> {
+            let seconds = Option::<u64>::deserialize(deserializer)?;
+            Ok(seconds.map(|seconds| super::UNIX_EPOCH + super::Duration::from_secs(seconds)))
+        }
+    }
+}
@@ -1,58 +1,280 @@
-use crate::fs::{Fs, Metadata};
-use std::path::Path;
+use crate::fs::{DirIterator, Fs, FsResult, Metadata};
+use std::collections::HashSet;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 #[derive(Debug, Default)]
 pub(crate) struct Stats {
     copied_count: AtomicI64,
     copied_size: AtomicU64,
+    collision_count: AtomicU64,
+    skipped_count: AtomicU64,
+    skipped_duplicates: AtomicU64,
+    skipped_by_filter: AtomicU64,
+    renamed_count: AtomicU64,
+    moved_count: AtomicU64,
+    symlinked_count: AtomicU64,
+    copy_durations: Mutex<Vec<Duration>>,
 }
 
+// Stats only contains atomics and a mutex, so it can be shared between threads as is.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Stats>();
+};
+
 impl Stats {
     fn count_file(&self, size: u64) {
         self.copied_count.fetch_add(1, Ordering::Relaxed);
         self.copied_size.fetch_add(size, Ordering::Relaxed);
     }
 
+    pub(crate) fn count_collision(&self) {
+        self.collision_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_skip(&self) {
+        self.skipped_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_duplicate(&self) {
+        self.skipped_duplicates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a source file skipped by the directory, name, extension or size filters.
+    pub(crate) fn count_filtered(&self) {
+        self.skipped_by_filter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_copy_duration(&self, duration: Duration) {
+        self.copy_durations.lock().unwrap().push(duration);
+    }
+
+    fn count_rename(&self) {
+        self.renamed_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn count_move(&self) {
+        self.moved_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn count_symlink(&self) {
+        self.symlinked_count.fetch_add(1, Ordering::Relaxed);
+    }
+
     pub(crate) fn copied_count(&self) -> i64 {
         self.copied_count.load(Ordering::Relaxed)
     }
     pub(crate) fn copied_size(&self) -> u64 {
         self.copied_size.load(Ordering::Relaxed)
     }
+    pub(crate) fn collision_count(&self) -> u64 {
+        self.collision_count.load(Ordering::Relaxed)
+    }
+    pub(crate) fn skipped_count(&self) -> u64 {
+        self.skipped_count.load(Ordering::Relaxed)
+    }
+    pub(crate) fn skipped_duplicates(&self) -> u64 {
+        self.skipped_duplicates.load(Ordering::Relaxed)
+    }
+    pub(crate) fn skipped_by_filter(&self) -> u64 {
+        self.skipped_by_filter.load(Ordering::Relaxed)
+    }
+    pub(crate) fn renamed_count(&self) -> u64 {
+        self.renamed_count.load(Ordering::Relaxed)
+    }
+    pub(crate) fn moved_count(&self) -> u64 {
+        self.moved_count.load(Ordering::Relaxed)
+    }
+    pub(crate) fn symlinked_count(&self) -> u64 {
+        self.symlinked_count.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn p50_copy_duration(&self) -> Duration {
+        self.copy_duration_percentile(50)
+    }
+
+    pub(crate) fn p99_copy_duration(&self) -> Duration {
+        self.copy_duration_percentile(99)
+    }
+
+    /// Nearest-rank percentile; durations are sorted only here to keep the copy path cheap.
+    fn copy_duration_percentile(&self, percentile: usize) -> Duration {
+        let mut durations = self.copy_durations.lock().unwrap().clone();
+        if durations.is_empty() {
+            return Duration::ZERO;
+        }
+        durations.sort_unstable();
+        let rank = (percentile * durations.len()).div_ceil(100);
+        durations[rank.saturating_sub(1)]
+    }
 }
 
-pub(crate) struct StatFs<T> {
+pub(crate) struct StatFs<T, S = Rc<Stats>> {
     fs: T,
-    stats: Rc<Stats>,
+    stats: S,
 }
 
-impl<T> StatFs<T> {
-    pub(crate) fn new(fs: T, stats: Rc<Stats>) -> Self {
+/// `StatFs` for single-threaded use, `Arc<Stats>` can be used to share the stats between threads.
+pub(crate) type LocalStatFs<T> = StatFs<T, Rc<Stats>>;
+
+impl<T, S: Deref<Target = Stats>> StatFs<T, S> {
+    pub(crate) fn new(fs: T, stats: S) -> Self {
         Self { fs, stats }
     }
 }
 
-impl<T: Fs> Fs for StatFs<T> {
+impl<T: Fs, S: Deref<Target = Stats>> Fs for StatFs<T, S> {
     fn name(&self) -> String {
         format!("StatFs({})", self.fs.name())
     }
-    fn create_dir_all(&self, path: &Path) -> anyhow::Result<()> {
+    fn create_dir_all(&self, path: &Path) -> FsResult<()> {
         self.fs.create_dir_all(path)
     }
 
-    fn metadata(&self, path: &Path) -> anyhow::Result<Metadata> {
+    fn metadata(&self, path: &Path) -> FsResult<Metadata> {
         self.fs.metadata(path)
     }
 
-    fn copy(&self, from: &Path, to: &Path) -> anyhow::Result<u64> {
+    fn copy(&self, from: &Path, to: &Path) -> FsResult<u64> {
+        let started = Instant::now();
         let size = self.fs.copy(from, to)?;
+        self.stats.record_copy_duration(started.elapsed());
         self.stats.count_file(size);
         Ok(size)
     }
 
+    fn copy_with_sha256(&self, from: &Path, to: &Path) -> FsResult<(u64, String)> {
+        let started = Instant::now();
+        let (size, sha256) = self.fs.copy_with_sha256(from, to)?;
+        self.stats.record_copy_duration(started.elapsed());
+        self.stats.count_file(size);
+        Ok((size, sha256))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.fs.rename(from, to)?;
+        self.stats.count_rename();
+        Ok(())
+    }
+
+    fn move_file(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.fs.move_file(from, to)?;
+        self.stats.count_move();
+        Ok(())
+    }
+
+    fn symlink(&self, original: &Path, link: &Path) -> FsResult<()> {
+        self.fs.symlink(original, link)?;
+        self.stats.count_symlink();
+        Ok(())
+    }
+
+    fn replace(&self, from: &Path, to: &Path) -> FsResult<()> {
+        self.fs.replace(from, to)
+    }
+
+    /// Tool-generated files are not counted as copied.
+    fn write_bytes(&self, path: &Path, data: &[u8]) -> FsResult<()> {
+        self.fs.write_bytes(path, data)
+    }
+
+    fn list_dir(&self, path: &Path, excluded_dirs: &HashSet<String>) -> FsResult<DirIterator> {
+        self.fs.list_dir(path, excluded_dirs)
+    }
+
     fn exists(&self, path: &Path) -> bool {
         self.fs.exists(path)
     }
+
+    fn sha256(&self, path: &Path) -> FsResult<String> {
+        self.fs.sha256(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> FsResult<String> {
+        self.fs.read_to_string(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> FsResult<PathBuf> {
+        self.fs.canonicalize(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::StdFs;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn count_file_from_two_threads() {
+        let stats = Arc::new(Stats::default());
+        let threads: Vec<_> = (0..2)
+            .map(|_| {
+                let stats = Arc::clone(&stats);
+                thread::spawn(move || stats.count_file(10))
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(stats.copied_count(), 2);
+        assert_eq!(stats.copied_size(), 20);
+    }
+
+    #[test]
+    fn shared_stat_fs_counts_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("a.jpg");
+        std::fs::write(&source, b"data").unwrap();
+        let stats = Arc::new(Stats::default());
+        let fs = StatFs::new(StdFs, Arc::clone(&stats));
+
+        thread::scope(|scope| {
+            for name in ["b.jpg", "c.jpg"] {
+                let (fs, source, dir) = (&fs, &source, dir.path());
+                scope.spawn(move || fs.copy(source, &dir.join(name)).unwrap());
+            }
+        });
+        assert_eq!(stats.copied_count(), 2);
+        assert_eq!(stats.copied_size(), 8);
+    }
+
+    #[test]
+    fn copy_duration_percentiles() {
+        let stats = Stats::default();
+        assert_eq!(stats.p50_copy_duration(), Duration::ZERO);
+
+        for millis in [50, 10, 40, 30, 20] {
+            stats.record_copy_duration(Duration::from_millis(millis));
+        }
+        assert_eq!(stats.p50_copy_duration(), Duration::from_millis(30));
+        assert_eq!(stats.p99_copy_duration(), Duration::from_millis(50));
+
+        for millis in 1..=95 {
+            stats.record_copy_duration(Duration::from_micros(millis));
+        }
+        assert_eq!(stats.p99_copy_duration(), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn written_files_are_not_counted_as_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let stats = Rc::new(Stats::default());
+        let fs = LocalStatFs::new(StdFs, Rc::clone(&stats));
+
+        fs.write_bytes(&dir.path().join("SHA256SUMS"), b"checksums")
+            .unwrap();
+        assert_eq!(
+            std::fs::read(dir.path().join("SHA256SUMS")).unwrap(),
+            b"checksums"
+        );
+        assert_eq!(stats.copied_count(), 0);
+        assert_eq!(stats.copied_size(), 0);
+    }
 }
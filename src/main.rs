@@ -1,19 +1,42 @@
+mod audit;
+mod config;
 pub(crate) mod fs;
+mod output;
+mod prompt;
+mod space;
+#[cfg(test)]
+mod test_util;
+mod watch;
 
+use crate::config::RawArgsConfig;
 use crate::fs::cow::CowFs;
-use crate::fs::dry::ObjectMap;
-use crate::fs::stat::{StatFs, Stats};
-use crate::fs::{Fs, Metadata};
-use anyhow::Context;
+use crate::fs::dry::{diff_object_maps, DiffEntry, ObjectMap};
+use crate::fs::limited::{LimitExceeded, LimitedFs};
+use crate::fs::stat::{LocalStatFs, Stats};
+use crate::fs::{is_in_excluded_dir, Fs, FsError, FsResult, FsStack, Metadata};
+use crate::output::{check_path_encoding, OutputEncoding, PathFormatter};
+use crate::prompt::{ConflictChoice, StdinPrompt, UserPrompt};
+use crate::space::{SpaceProvider, SystemSpace};
+use anyhow::{bail, Context};
 use argh::FromArgs;
+use chrono::format::{Item, StrftimeItems};
 use chrono::{DateTime, Utc};
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use mediameta::extract_file_creation_date;
-use reflink_copy::ReflinkSupport;
+use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
 use std::fs::File;
-use std::io::{self, Write};
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::str::FromStr;
+use std::time::SystemTime;
+use tracing::{debug, info, warn, Level};
+use tracing_subscriber::fmt::format::FmtSpan;
+
+const CHECKSUM_FILE_NAME: &str = "SHA256SUMS";
 
 /// Organize a media library by creation date, moving media files from source to target directory.
 #[derive(FromArgs)]
@@ -27,25 +50,270 @@ struct RawArgs {
     target: String,
 
     /// name of the subfolder for unrecognized media files.
-    #[argh(option, default = "\"unrecognized\".to_string()")]
-    unrecognized: String,
+    /// Default: unrecognized
+    #[argh(option)]
+    unrecognized: Option<String>,
 
     /// custom pattern for organizing the target directory based on media creation time.
     /// The resulting path will be structured in subfolders.
     /// Default: %Y/%m/%d
-    #[argh(option, default = "\"%Y/%m/%d\".to_string()")]
-    target_dir_pattern: String,
+    #[argh(option)]
+    target_dir_pattern: Option<String>,
 
     /// custom pattern for naming the target file based on media creation time.
     /// The resulting name should be a valid filename.
     /// Default: %Y-%m-%dT%H%M%S
-    #[argh(option, default = "\"%Y-%m-%dT%H%M%S\".to_string()")]
-    target_file_pattern: String,
+    #[argh(option)]
+    target_file_pattern: Option<String>,
+
+    /// custom directory and file patterns for a specific file extension in the format
+    /// `EXT=DIR_PATTERN:FILE_PATTERN`, e.g. `mp4=videos/%Y/%m:%Y-%m-%dT%H%M%S`.
+    /// Can be specified multiple times.
+    #[argh(option)]
+    ext_pattern: Vec<String>,
+
+    /// source of the file creation date: `exif` (default), `created`, `modified`,
+    /// or `exif-then-created-then-modified` to try them in that order.
+    #[argh(option)]
+    date_source: Option<DateSource>,
+
+    /// what to do if a different file with the same name already exists in the target:
+    /// `rename` (default) keeps both by appending `_N` to the new file, `keep-older`,
+    /// `keep-newer` and `keep-larger` keep one of the files, `skip` keeps the existing file.
+    #[argh(option)]
+    conflict_strategy: Option<ConflictStrategy>,
+
+    /// ask what to do each time a different file with the same name already exists in the target.
+    /// Overrides --conflict-strategy. Cannot be used with --dry-run.
+    #[argh(switch)]
+    interactive: bool,
+
+    /// log level: `error`, `warn`, `info` (default), `debug`, or `trace`.
+    #[argh(option)]
+    log_level: Option<Level>,
+
+    /// print additional details about the run.
+    #[argh(switch)]
+    verbose: bool,
+
+    /// use reflink (copy-on-write) instead of copying files if the target filesystem supports it.
+    #[argh(switch)]
+    use_reflink: bool,
 
     /// simulates the run, outputting all file copy operations without making changes.
     /// WARNING: Stores metadata of all copied files in memory for duplicate detection.
     #[argh(switch)]
     dry_run: bool,
+
+    /// rename files in place using the target file pattern instead of copying them
+    /// into the target directory. The target directory and its patterns are ignored.
+    #[argh(switch)]
+    rename_only: bool,
+
+    /// move files into the target directory instead of copying them. Files on another
+    /// device are copied and then deleted from the source.
+    #[argh(switch, long = "move")]
+    move_files: bool,
+
+    /// create symlinks in the target pointing to the resolved source files instead of copying
+    /// the content of symlinked source files.
+    #[argh(switch)]
+    copy_symlinks_as_symlinks: bool,
+
+    /// keep running after the initial sync and process new or modified files
+    /// in the source directory until interrupted with Ctrl-C.
+    #[argh(switch)]
+    watch: bool,
+
+    /// scan the whole source directory first and print how many files will be synced
+    /// before copying anything.
+    #[argh(switch)]
+    two_pass: bool,
+
+    /// scan the target directory and print file counts and sizes per month, months without files,
+    /// and files not matching the target file pattern, without syncing anything.
+    #[argh(switch)]
+    report_only: bool,
+
+    /// skip files without a recognizable creation date instead of copying them
+    /// into the unrecognized folder. Such files are still listed in the output.
+    #[argh(switch)]
+    skip_unrecognized: bool,
+
+    /// name of a source subdirectory to skip, e.g. `@eaDir` or `.git`.
+    /// Can be specified multiple times.
+    #[argh(option)]
+    exclude_dir: Vec<String>,
+
+    /// glob pattern for file names that should be processed, e.g. `IMG_*.jpg`.
+    /// Can be specified multiple times. If omitted, all files are processed.
+    #[argh(option)]
+    source_filter: Vec<String>,
+
+    /// glob pattern for file names that should be skipped, e.g. `*.tmp`.
+    /// Can be specified multiple times.
+    #[argh(option)]
+    source_exclude: Vec<String>,
+
+    /// put all files directly into the target directory without date-based subfolders.
+    /// The target directory pattern is ignored in this mode.
+    #[argh(switch)]
+    flat: bool,
+
+    /// keep the original case of file extensions instead of converting them to lowercase.
+    #[argh(switch)]
+    preserve_extension_case: bool,
+
+    /// skip files smaller than the specified size in bytes.
+    #[argh(option)]
+    size_min: Option<u64>,
+
+    /// skip files larger than the specified size in bytes.
+    #[argh(option)]
+    size_max: Option<u64>,
+
+    /// abort the sync if copying a file would leave less than the specified number of bytes
+    /// of free space on the target disk.
+    #[argh(option)]
+    min_free_space: Option<u64>,
+
+    /// write a SHA256SUMS file with checksums of synced files into every target directory.
+    /// The resulting file can be verified with `sha256sum --check`.
+    #[argh(switch)]
+    generate_checksums: bool,
+
+    /// stop the sync after copying the specified number of files.
+    #[argh(option)]
+    max_files: Option<u64>,
+
+    /// stop the sync after copying the specified number of bytes.
+    #[argh(option)]
+    max_bytes: Option<u64>,
+
+    /// print the number of files copied into each target directory after the sync.
+    #[argh(switch)]
+    dir_stats: bool,
+
+    /// print copy duration percentiles after the sync.
+    #[argh(switch)]
+    perf: bool,
+
+    /// path to a JSON file where the dry run results will be saved. Requires --dry-run.
+    #[argh(option)]
+    dry_run_save: Option<String>,
+
+    /// path to a JSON file with previously saved dry run results to compare the current run against.
+    /// Requires --dry-run. If specified twice, compares the two files without running the sync.
+    #[argh(option)]
+    dry_run_diff: Option<String>,
+
+    /// format of the `unknown_files` log written into the unrecognized folder: `text` (default),
+    /// one path per line, or `json`, an array of objects with the path and the reason.
+    #[argh(option)]
+    unknown_log_format: Option<UnknownLogFormat>,
+
+    /// how paths are written to logs and the dry run output: `utf8-lossy` (default), replacing
+    /// invalid sequences, `utf8-strict`, failing on non UTF-8 source paths, or `percent-encoded`,
+    /// writing invalid bytes as `%XX`.
+    #[argh(option)]
+    output_encoding: Option<OutputEncoding>,
+
+    /// path to a TOML file with default values for the options above, using their names
+    /// with underscores, e.g. `target_dir_pattern = "%Y/%m"`. Command line options take precedence.
+    #[argh(option)]
+    config: Option<String>,
+}
+
+/// Compare two saved dry run results without running the sync.
+#[derive(FromArgs)]
+struct DryRunDiffArgs {
+    /// paths to the JSON files with dry run results, the first one is compared against the second one.
+    #[argh(option)]
+    dry_run_diff: Vec<String>,
+
+    /// how paths are written to the diff: `utf8-lossy` (default), `utf8-strict` or `percent-encoded`.
+    #[argh(option)]
+    output_encoding: Option<OutputEncoding>,
+}
+
+impl DryRunDiffArgs {
+    /// The standalone diff mode is used when `--dry-run-diff` is given twice.
+    fn requested(args: impl IntoIterator<Item = String>) -> bool {
+        args.into_iter()
+            .filter(|arg| arg == "--dry-run-diff")
+            .count()
+            == 2
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum DateSource {
+    Exif,
+    Created,
+    Modified,
+    ExifThenCreatedThenModified,
+}
+
+impl FromStr for DateSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exif" => Ok(Self::Exif),
+            "created" => Ok(Self::Created),
+            "modified" => Ok(Self::Modified),
+            "exif-then-created-then-modified" => Ok(Self::ExifThenCreatedThenModified),
+            _ => Err(format!(
+                "unknown date source [{s}], expected one of: exif, created, modified, exif-then-created-then-modified"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ConflictStrategy {
+    Rename,
+    KeepOlder,
+    KeepNewer,
+    KeepLarger,
+    Skip,
+}
+
+impl FromStr for ConflictStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rename" => Ok(Self::Rename),
+            "keep-older" => Ok(Self::KeepOlder),
+            "keep-newer" => Ok(Self::KeepNewer),
+            "keep-larger" => Ok(Self::KeepLarger),
+            "skip" => Ok(Self::Skip),
+            _ => Err(format!(
+                "unknown conflict strategy [{s}], expected one of: rename, keep-older, keep-newer, keep-larger, skip"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum UnknownLogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for UnknownLogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!(
+                "unknown log format [{s}], expected one of: text, json"
+            )),
+        }
+    }
 }
 
 struct Args {
@@ -54,24 +322,178 @@ struct Args {
     pub unrecognized: PathBuf,
     pub target_dir_pattern: String,
     pub target_file_pattern: String,
+    pub ext_patterns: HashMap<String, (String, String)>,
+    pub date_source: DateSource,
+    pub conflict_strategy: ConflictStrategy,
     pub dry_run: bool,
+    pub skip_unrecognized: bool,
+    pub rename_only: bool,
+    pub move_files: bool,
+    pub copy_symlinks_as_symlinks: bool,
+    pub watch: bool,
+    pub two_pass: bool,
+    pub report_only: bool,
+    pub excluded_dirs: HashSet<String>,
+    pub source_filter: GlobSet,
+    pub source_exclude: GlobSet,
+    pub flat: bool,
+    pub preserve_extension_case: bool,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub min_free_space: Option<u64>,
+    pub generate_checksums: bool,
+    pub dir_stats: bool,
+    pub perf: bool,
+    pub dry_run_save: Option<PathBuf>,
+    pub dry_run_diff: Option<PathBuf>,
+    pub unknown_log_format: UnknownLogFormat,
+    pub output_encoding: OutputEncoding,
     pub fs: Box<dyn Fs>,
+    pub stats: Rc<Stats>,
+    /// Asks what to do on name collisions in `--interactive` mode.
+    pub prompt: Option<Box<dyn UserPrompt>>,
+    /// Checks the free space on the target for `--min-free-space`.
+    pub space: Box<dyn SpaceProvider>,
 }
 
 impl Args {
-    fn new(value: RawArgs, fs: Box<dyn Fs>) -> Self {
+    fn new(value: RawArgs, fs: Box<dyn Fs>, stats: Rc<Stats>) -> anyhow::Result<Self> {
+        let target_dir_pattern = value
+            .target_dir_pattern
+            .unwrap_or_else(|| "%Y/%m/%d".to_string());
+        let target_file_pattern = value
+            .target_file_pattern
+            .unwrap_or_else(|| "%Y-%m-%dT%H%M%S".to_string());
+        Self::validate_dir_pattern(&target_dir_pattern)?;
+        Self::validate_file_pattern(&target_file_pattern)?;
+        if !value.dry_run && (value.dry_run_save.is_some() || value.dry_run_diff.is_some()) {
+            bail!("--dry-run-save and --dry-run-diff can only be used together with --dry-run");
+        }
+        if value.interactive && value.dry_run {
+            bail!("--interactive cannot be used together with --dry-run");
+        }
+        if value.report_only && (value.watch || value.rename_only) {
+            bail!("--report-only cannot be used together with --watch or --rename-only");
+        }
+        if value.move_files && value.rename_only {
+            bail!("--move cannot be used together with --rename-only");
+        }
+
         let current_date = Utc::now().format("%Y-%m-%dT%H%M%S").to_string();
         let target: PathBuf = Self::fix_separator(&value.target).into();
-        let unrecognized = target.join(&value.unrecognized).join(&current_date);
-        Self {
+        let unrecognized = target
+            .join(value.unrecognized.as_deref().unwrap_or("unrecognized"))
+            .join(&current_date);
+        let output_encoding = value.output_encoding.unwrap_or_default();
+        Ok(Self {
             source: Self::fix_separator(&value.source).into(),
             target,
             unrecognized,
-            target_dir_pattern: Self::fix_separator(&value.target_dir_pattern),
-            target_file_pattern: value.target_file_pattern,
+            target_dir_pattern: Self::fix_separator(&target_dir_pattern),
+            target_file_pattern,
+            ext_patterns: Self::parse_ext_patterns(&value.ext_pattern)?,
+            date_source: value.date_source.unwrap_or(DateSource::Exif),
+            conflict_strategy: value.conflict_strategy.unwrap_or(ConflictStrategy::Rename),
             dry_run: value.dry_run,
+            skip_unrecognized: value.skip_unrecognized,
+            rename_only: value.rename_only,
+            move_files: value.move_files,
+            copy_symlinks_as_symlinks: value.copy_symlinks_as_symlinks,
+            watch: value.watch,
+            two_pass: value.two_pass,
+            report_only: value.report_only,
+            excluded_dirs: value.exclude_dir.into_iter().collect(),
+            source_filter: Self::build_glob_set(&value.source_filter)?,
+            source_exclude: Self::build_glob_set(&value.source_exclude)?,
+            flat: value.flat,
+            preserve_extension_case: value.preserve_extension_case,
+            min_size: value.size_min,
+            max_size: value.size_max,
+            min_free_space: value.min_free_space,
+            generate_checksums: value.generate_checksums,
+            dir_stats: value.dir_stats,
+            perf: value.perf,
+            dry_run_save: value.dry_run_save.map(PathBuf::from),
+            dry_run_diff: value.dry_run_diff.map(PathBuf::from),
+            unknown_log_format: value.unknown_log_format.unwrap_or(UnknownLogFormat::Text),
+            output_encoding,
             fs,
+            stats,
+            prompt: value.interactive.then(|| {
+                Box::new(StdinPrompt {
+                    encoding: output_encoding,
+                }) as Box<dyn UserPrompt>
+            }),
+            space: Box::new(SystemSpace),
+        })
+    }
+
+    /// Returns the target dir and file patterns, taking `--ext-pattern` into account.
+    fn patterns_for(&self, path: &Path) -> (&str, &str) {
+        path.extension()
+            .and_then(|ext| self.ext_patterns.get(&ext.to_string_lossy().to_lowercase()))
+            .map(|(dir, file)| (dir.as_str(), file.as_str()))
+            .unwrap_or((&self.target_dir_pattern, &self.target_file_pattern))
+    }
+
+    fn fmt_path<'a>(&self, path: &'a Path) -> PathFormatter<'a> {
+        PathFormatter::new(path, self.output_encoding)
+    }
+
+    fn render_pattern(pattern: &str) -> anyhow::Result<String> {
+        if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+            bail!("Invalid date pattern [{pattern}]: unknown format specifier");
+        }
+        Ok(Utc::now().format(pattern).to_string())
+    }
+
+    fn validate_dir_pattern(pattern: &str) -> anyhow::Result<()> {
+        Self::render_pattern(pattern)
+            .with_context(|| "Failed to validate target directory pattern")?;
+        Ok(())
+    }
+
+    fn validate_file_pattern(pattern: &str) -> anyhow::Result<()> {
+        let rendered = Self::render_pattern(pattern)
+            .with_context(|| "Failed to validate target file pattern")?;
+        if rendered.contains(['/', '\\']) {
+            bail!(
+                "Invalid target file pattern [{pattern}]: the resulting filename cannot contain path separators"
+            );
+        }
+        Ok(())
+    }
+
+    fn parse_ext_patterns(values: &[String]) -> anyhow::Result<HashMap<String, (String, String)>> {
+        let mut patterns = HashMap::new();
+        for value in values {
+            let (extension, dir_pattern, file_pattern) = value
+                .split_once('=')
+                .and_then(|(ext, patterns)| {
+                    let (dir, file) = patterns.split_once(':')?;
+                    Some((ext, dir, file))
+                })
+                .with_context(|| {
+                    format!("Invalid extension pattern [{value}], expected EXT=DIR_PATTERN:FILE_PATTERN")
+                })?;
+            Self::validate_dir_pattern(dir_pattern)?;
+            Self::validate_file_pattern(file_pattern)?;
+            patterns.insert(
+                extension.trim_start_matches('.').to_lowercase(),
+                (Self::fix_separator(dir_pattern), file_pattern.to_string()),
+            );
+        }
+        Ok(patterns)
+    }
+
+    fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob =
+                Glob::new(pattern).with_context(|| format!("Invalid glob pattern [{pattern}]"))?;
+            builder.add(glob);
         }
+        Ok(builder.build()?)
     }
 
     fn fix_separator(s: &str) -> String {
@@ -80,72 +502,175 @@ impl Args {
     }
 }
 
+fn init_tracing(level: Level) {
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_target(false)
+        .with_timer(())
+        .init();
+}
+
 fn main() -> anyhow::Result<()> {
-    let args: RawArgs = argh::from_env();
+    if DryRunDiffArgs::requested(std::env::args()) {
+        let args: DryRunDiffArgs = argh::from_env();
+        init_tracing(Level::INFO);
+        let [before, after] = args.dry_run_diff.as_slice() else {
+            bail!("--dry-run-diff expects exactly two files to compare");
+        };
+        let encoding = args.output_encoding.unwrap_or_default();
+        let before = load_dry_run_results(Path::new(before), encoding)?;
+        let after = load_dry_run_results(Path::new(after), encoding)?;
+        print_dry_run_diff(&before, &after, encoding);
+        return Ok(());
+    }
+
+    let mut args: RawArgs = argh::from_env();
+    if let Some(config) = &args.config {
+        RawArgsConfig::load(Path::new(config))?.apply(&mut args)?;
+    }
+    init_tracing(args.log_level.unwrap_or(Level::INFO));
+
     let mut ctx = AppContext::default();
 
     let stats = Rc::new(Stats::default());
-    let mut dry_fs_objects = None;
+    let dry_fs_objects = Rc::new(RefCell::new(ObjectMap::new()));
 
     let fs: Box<dyn Fs> = if args.dry_run {
-        dry_fs_objects = Some(RefCell::new(ObjectMap::new()));
-        Box::new(StatFs::new(
-            fs::DryFs::new(
-                fs::ErrorContextFs::new(fs::StdFs),
-                RefCell::clone(dry_fs_objects.as_ref().unwrap()),
+        Box::new(LocalStatFs::new(
+            LimitedFs::new(
+                fs::DryFs::new(
+                    fs::ErrorContextFs::new(fs::StdFs),
+                    Rc::clone(&dry_fs_objects),
+                ),
+                args.max_files,
+                args.max_bytes,
             ),
             Rc::clone(&stats),
         ))
     } else {
-        let reflink_support = reflink_copy::check_reflink_support(&args.source, &args.target);
-        println!("reflink support: {:?}", reflink_support);
-
-        let make_default_fs = || {
-            Box::new(StatFs::new(
-                fs::ErrorContextFs::new(fs::StdFs),
+        if args.use_reflink {
+            let cow_fs = CowFs::auto(fs::StdFs, Path::new(&args.source), Path::new(&args.target))?;
+            info!("CoW fs will be used");
+            Box::new(LocalStatFs::new(
+                LimitedFs::new(
+                    fs::ErrorContextFs::new(cow_fs),
+                    args.max_files,
+                    args.max_bytes,
+                ),
+                Rc::clone(&stats),
+            ))
+        } else {
+            Box::new(LocalStatFs::new(
+                LimitedFs::new(
+                    fs::ErrorContextFs::new(fs::StdFs),
+                    args.max_files,
+                    args.max_bytes,
+                ),
                 Rc::clone(&stats),
             ))
-        };
-
-        match reflink_support {
-            Ok(ReflinkSupport::NotSupported) => {
-                println!("reflink support is not supported");
-                make_default_fs()
-            }
-            Err(e) => {
-                eprintln!("check_reflink_support returned an error: {:?}", e);
-                make_default_fs()
-            }
-            Ok(reflink_support) => {
-                println!("CoW fs will be used");
-                Box::new(StatFs::new(
-                    fs::ErrorContextFs::new(CowFs::new(fs::StdFs, reflink_support)),
-                    Rc::clone(&stats),
-                ))
-            }
         }
     };
 
-    println!("Fs: {}", fs.name());
-    let args = Args::new(args, fs);
-    let unrecognized_files = sync_media(&mut ctx, &args)?;
+    if args.verbose {
+        info!("Fs: {}", FsStack::describe(fs.as_ref()));
+    } else {
+        info!("Fs: {}", fs.name());
+    }
+    let args = Args::new(args, fs, Rc::clone(&stats))?;
+    if args.report_only {
+        let report = audit::audit_target(&args, args.fs.as_ref())?;
+        audit::print_audit_report(&args, &report);
+        return Ok(());
+    }
+    let mut result = sync_media(&mut ctx, &args)?;
+    if args.watch {
+        watch::watch_source(&mut ctx, &args, &mut result)?;
+    }
+    if args.generate_checksums {
+        write_checksums(&ctx, &args)?;
+    }
 
     if args.dry_run {
-        println!("Dry run results:");
-        print_dry_run(&dry_fs_objects.unwrap().borrow());
-        print_unknown_files(&unrecognized_files);
-    } else if !unrecognized_files.is_empty() {
-        log_unknown_files(&args, &unrecognized_files)?;
+        info!("Dry run results:");
+        let objects = dry_fs_objects.borrow();
+        if args.rename_only {
+            print_dry_run_renames(&objects, args.output_encoding);
+        } else {
+            print_dry_run(&objects, args.output_encoding);
+        }
+        print_unknown_files(&result.unrecognized_files, args.output_encoding);
+        if let Some(path) = &args.dry_run_save {
+            std::fs::write(path, objects.to_json()?).with_context(|| {
+                format!(
+                    "Failed to save dry run results to [{}]",
+                    args.fmt_path(path)
+                )
+            })?;
+        }
+        if let Some(path) = &args.dry_run_diff {
+            let before = load_dry_run_results(path, args.output_encoding)?;
+            // round trip the current results to get the same timestamp precision
+            let after = ObjectMap::from_json(&objects.to_json()?)?;
+            print_dry_run_diff(&before, &after, args.output_encoding);
+        }
+    } else if args.skip_unrecognized || args.rename_only {
+        print_unknown_files(&result.unrecognized_files, args.output_encoding);
+    } else if !result.unrecognized_files.is_empty() {
+        make_path(&mut ctx, &args, &args.unrecognized)?;
+        log_unknown_files(&args, &result.unrecognized_files)?;
     };
 
-    println!("Copied files: {}", stats.copied_count());
-    println!("Copied data size: {}", stats.copied_size());
+    if args.dir_stats {
+        print_dir_stats(&ctx, args.output_encoding);
+    }
+
+    print_summary(&args, &result);
     Ok(())
 }
 
+/// Prints the final stats.
+fn print_summary(args: &Args, result: &SyncResult) {
+    let stats = &args.stats;
+    if args.perf {
+        info!("Copy duration p50: {:?}", stats.p50_copy_duration());
+        info!("Copy duration p99: {:?}", stats.p99_copy_duration());
+    }
+
+    info!("Total files: {}", result.total_files(stats));
+    info!("Copied files: {}", stats.copied_count());
+    info!("Copied data size: {}", stats.copied_size());
+    info!("Renamed due to collision: {}", stats.collision_count());
+    info!("Skipped due to collision: {}", stats.skipped_count());
+    info!("Skipped duplicates: {}", stats.skipped_duplicates());
+    info!("Skipped by filter: {}", stats.skipped_by_filter());
+    info!("Renamed in place: {}", stats.renamed_count());
+    info!("Moved files: {}", stats.moved_count());
+    info!("Symlinked files: {}", stats.symlinked_count());
+    info!(
+        "Not processed due to copy limit: {}",
+        result.limit_reached.len()
+    );
+    info!(
+        "Skipped by size filter: {}",
+        result.size_filtered_files.len()
+    );
+    info!(
+        "Skipped (extension excluded): {}",
+        result.extension_excluded_count
+    );
+    info!("Skipped (EXIF parse failed): {}", result.exif_failed_count);
+    info!(
+        "Copied to unrecognized (EXIF parse failed): {}",
+        result.exif_failed_unrecognized_count
+    );
+}
+
 #[derive(Default, Debug)]
 struct AppContext {
     created_dirs: std::collections::HashSet<PathBuf>,
+    checksums: HashMap<PathBuf, Vec<(String, String)>>,
+    dir_file_counts: HashMap<PathBuf, u64>,
 }
 
 fn make_path(ctx: &mut AppContext, args: &Args, path: &Path) -> anyhow::Result<()> {
@@ -158,39 +683,324 @@ fn make_path(ctx: &mut AppContext, args: &Args, path: &Path) -> anyhow::Result<(
     Ok(())
 }
 
-fn sync_media(ctx: &mut AppContext, args: &Args) -> anyhow::Result<Vec<PathBuf>> {
-    let mut unrecognized_files: Vec<PathBuf> = Vec::new();
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum UnrecognizedReason {
+    ExtensionExcluded,
+    ExifFailed,
+}
 
-    make_path(ctx, args, &args.target)?;
-    for entry in walkdir::WalkDir::new(&args.source) {
-        let entry = entry.with_context(|| "Failed to enumerate source directory")?;
-        let path = entry.path();
-        if path.is_file() {
-            if !can_be_media_file(path) {
-                unrecognized_files.push(path.to_path_buf());
-                continue;
-            }
-            let creation_date = extract_file_creation_date(path);
-            if creation_date.is_err() {
-                process_unrecognized_file(ctx, args, path).with_context(|| {
-                    format!("Failed to process the file [{}]", path.to_string_lossy())
-                })?;
-                unrecognized_files.push(path.to_path_buf());
-                continue;
+#[derive(Debug, Clone, PartialEq)]
+struct UnrecognizedFile {
+    path: PathBuf,
+    reason: UnrecognizedReason,
+    /// Source directory the file was found in.
+    source_root: PathBuf,
+}
+
+impl UnrecognizedFile {
+    fn new(args: &Args, path: &Path, reason: UnrecognizedReason) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            reason,
+            source_root: args.source.clone(),
+        }
+    }
+}
+
+/// Entry of the JSON unknown files log, paths are written using `--output-encoding`
+/// as JSON strings cannot hold non UTF-8 paths.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct UnrecognizedFileEntry {
+    path: String,
+    reason: UnrecognizedReason,
+    source_root: String,
+}
+
+impl UnrecognizedFileEntry {
+    fn new(file: &UnrecognizedFile, encoding: OutputEncoding) -> Self {
+        Self {
+            path: PathFormatter::new(&file.path, encoding).to_string(),
+            reason: file.reason,
+            source_root: PathFormatter::new(&file.source_root, encoding).to_string(),
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+struct SyncResult {
+    unrecognized_files: Vec<UnrecognizedFile>,
+    /// Files skipped by the size filters, also counted in [`Stats::skipped_by_filter`].
+    size_filtered_files: Vec<PathBuf>,
+    /// Files skipped by extension, also counted in [`Stats::skipped_by_filter`].
+    extension_excluded_count: u64,
+    /// Files skipped because their date couldn't be extracted.
+    exif_failed_count: u64,
+    /// Files processed into the unrecognized folder because their date couldn't be extracted,
+    /// also counted as copied, moved or symlinked.
+    exif_failed_unrecognized_count: u64,
+    limit_reached: Vec<PathBuf>,
+}
+
+impl SyncResult {
+    /// Number of source files seen, every file is counted in exactly one of the summary categories.
+    fn total_files(&self, stats: &Stats) -> u64 {
+        stats.copied_count() as u64
+            + stats.renamed_count()
+            + stats.moved_count()
+            + stats.symlinked_count()
+            + stats.skipped_count()
+            + stats.skipped_duplicates()
+            + stats.skipped_by_filter()
+            + self.limit_reached.len() as u64
+            + self.exif_failed_count
+    }
+}
+
+/// A source file found during the scan phase, see [`plan_sync`].
+#[derive(Debug)]
+struct PlannedCopy {
+    source: PathBuf,
+    len: u64,
+    /// `None` if the date couldn't be extracted and the file goes into the unrecognized folder.
+    creation_date: Option<DateTime<Utc>>,
+}
+
+/// Files to sync collected in the first pass of `--two-pass` mode.
+#[derive(Debug, Default)]
+struct SyncPlan {
+    files: Vec<PlannedCopy>,
+    /// Files skipped during the scan.
+    result: SyncResult,
+}
+
+impl SyncPlan {
+    fn total_size(&self) -> u64 {
+        self.files.iter().map(|file| file.len).sum()
+    }
+}
+
+#[tracing::instrument(skip_all)]
+fn sync_media(ctx: &mut AppContext, args: &Args) -> anyhow::Result<SyncResult> {
+    if args.two_pass {
+        let plan = plan_sync(args)?;
+        info!(
+            "Sync plan: will copy {} files, {:.2} GB",
+            plan.files.len(),
+            plan.total_size() as f64 / 1e9
+        );
+        if let Some(prompt) = &args.prompt {
+            if !prompt.confirm("Start the sync?")? {
+                bail!("The sync has been cancelled by the user");
             }
-            let creation_date: DateTime<Utc> = creation_date.unwrap().into();
-            process_file(ctx, args, path, &args.target, &creation_date)
-                .with_context(|| format!("Failed to process file [{}]", path.to_string_lossy()))?;
         }
+        // Target names depend on the files already copied, so a dry run executes the plan
+        // against `DryFs` as well instead of stopping after the scan.
+        return execute_plan(ctx, args, plan);
+    }
+
+    let mut result = SyncResult::default();
+    if !args.rename_only {
+        make_path(ctx, args, &args.target)?;
+    }
+    let paths: Box<dyn Iterator<Item = FsResult<PathBuf>>> = if args.rename_only {
+        // renamed files may show up again in a directory that is still being read,
+        // so the source is listed completely before anything is renamed
+        Box::new(source_files(args)?.collect::<Vec<_>>().into_iter())
+    } else {
+        Box::new(source_files(args)?)
+    };
+    for path in paths {
+        let path = path?;
+        sync_within_limit(&mut result, &path, |result| {
+            sync_file(ctx, args, &path, result)
+        })?;
+    }
+
+    Ok(result)
+}
+
+/// Scans the source directory without changing anything.
+fn plan_sync(args: &Args) -> anyhow::Result<SyncPlan> {
+    let mut plan = SyncPlan::default();
+    for path in source_files(args)? {
+        let path = path?;
+        if let Some(planned) = plan_file(args, &path, &mut plan.result)? {
+            plan.files.push(planned);
+        }
+    }
+    Ok(plan)
+}
+
+fn execute_plan(ctx: &mut AppContext, args: &Args, plan: SyncPlan) -> anyhow::Result<SyncResult> {
+    let mut result = plan.result;
+    if !args.rename_only {
+        make_path(ctx, args, &args.target)?;
+    }
+    for planned in &plan.files {
+        sync_within_limit(&mut result, &planned.source, |result| {
+            execute_planned(ctx, args, planned, result)
+        })?;
+    }
+    Ok(result)
+}
+
+fn source_files(args: &Args) -> anyhow::Result<impl Iterator<Item = FsResult<PathBuf>>> {
+    let entries = args.fs.list_dir(&args.source, &args.excluded_dirs)?;
+    Ok(entries.filter_map(|entry| match entry {
+        Ok(entry) if entry.metadata.is_file() => Some(Ok(entry.path)),
+        Ok(_) => None,
+        Err(e) => Some(Err(e.context("Failed to enumerate source directory"))),
+    }))
+}
+
+/// Runs `sync` for the file unless the copy limit has been reached.
+/// Exceeding the limit is not an error, the remaining files are only collected in [`SyncResult::limit_reached`].
+fn sync_within_limit(
+    result: &mut SyncResult,
+    path: &Path,
+    sync: impl FnOnce(&mut SyncResult) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    if !result.limit_reached.is_empty() {
+        result.limit_reached.push(path.to_path_buf());
+        return Ok(());
+    }
+    if let Err(e) = sync(result) {
+        if is_disk_full(&e) {
+            return Err(e.context("Target disk is full, the sync has been aborted"));
+        }
+        if !is_limit_exceeded(&e) {
+            return Err(e);
+        }
+        warn!("Stopping the sync: {}", e.root_cause());
+        result.limit_reached.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn fs_error(e: &anyhow::Error) -> Option<&FsError> {
+    e.chain().find_map(|cause| cause.downcast_ref::<FsError>())
+}
+
+fn is_limit_exceeded(e: &anyhow::Error) -> bool {
+    fs_error(e).is_some_and(|e| e.inner().is::<LimitExceeded>())
+}
+
+fn is_disk_full(e: &anyhow::Error) -> bool {
+    matches!(fs_error(e), Some(FsError::DiskFull(_)))
+}
+
+fn sync_file(
+    ctx: &mut AppContext,
+    args: &Args,
+    path: &Path,
+    result: &mut SyncResult,
+) -> anyhow::Result<()> {
+    match plan_file(args, path, result)? {
+        Some(planned) => execute_planned(ctx, args, &planned, result),
+        None => Ok(()),
+    }
+}
+
+/// Applies the filters and extracts the creation date, returns `None` if the file is skipped.
+fn plan_file(
+    args: &Args,
+    path: &Path,
+    result: &mut SyncResult,
+) -> anyhow::Result<Option<PlannedCopy>> {
+    check_path_encoding(path, args.output_encoding)?;
+    if is_in_excluded_dir(&args.source, path, &args.excluded_dirs)
+        || !matches_source_filters(args, path)
+    {
+        args.stats.count_filtered();
+        return Ok(None);
+    }
+    if is_excluded_extension(path) {
+        result.unrecognized_files.push(UnrecognizedFile::new(
+            args,
+            path,
+            UnrecognizedReason::ExtensionExcluded,
+        ));
+        result.extension_excluded_count += 1;
+        args.stats.count_filtered();
+        return Ok(None);
+    }
+    let source_metadata = args.fs.metadata(path)?;
+    if !matches_size_filters(args, &source_metadata) {
+        result.size_filtered_files.push(path.to_path_buf());
+        args.stats.count_filtered();
+        return Ok(None);
+    }
+    let creation_date = file_creation_date(args, path, &source_metadata);
+    if creation_date.is_none() && (args.skip_unrecognized || args.rename_only) {
+        push_exif_failed(args, path, result);
+        result.exif_failed_count += 1;
+        return Ok(None);
+    }
+    Ok(Some(PlannedCopy {
+        source: path.to_path_buf(),
+        len: source_metadata.len(),
+        creation_date: creation_date.map(DateTime::<Utc>::from),
+    }))
+}
+
+fn execute_planned(
+    ctx: &mut AppContext,
+    args: &Args,
+    planned: &PlannedCopy,
+    result: &mut SyncResult,
+) -> anyhow::Result<()> {
+    let path = planned.source.as_path();
+    let Some(creation_date) = &planned.creation_date else {
+        process_unrecognized_file(ctx, args, path)
+            .with_context(|| format!("Failed to process the file [{}]", args.fmt_path(path)))?;
+        push_exif_failed(args, path, result);
+        result.exif_failed_unrecognized_count += 1;
+        return Ok(());
+    };
+    process_file(ctx, args, path, &args.target, creation_date)
+        .with_context(|| format!("Failed to process file [{}]", args.fmt_path(path)))
+}
+
+fn push_exif_failed(args: &Args, path: &Path, result: &mut SyncResult) {
+    result.unrecognized_files.push(UnrecognizedFile::new(
+        args,
+        path,
+        UnrecognizedReason::ExifFailed,
+    ));
+}
+
+fn file_creation_date(args: &Args, path: &Path, metadata: &Metadata) -> Option<SystemTime> {
+    match args.date_source {
+        DateSource::Exif => extract_file_creation_date(path).ok(),
+        DateSource::Created => metadata.created(),
+        DateSource::Modified => Some(metadata.modified()),
+        DateSource::ExifThenCreatedThenModified => extract_file_creation_date(path)
+            .ok()
+            .or(metadata.created())
+            .or(Some(metadata.modified())),
+    }
+}
+
+fn matches_source_filters(args: &Args, path: &Path) -> bool {
+    let Some(file_name) = path.file_name() else {
+        return false;
+    };
+    if !args.source_filter.is_empty() && !args.source_filter.is_match(file_name) {
+        return false;
     }
+    !args.source_exclude.is_match(file_name)
+}
 
-    Ok(unrecognized_files)
+fn matches_size_filters(args: &Args, metadata: &Metadata) -> bool {
+    let len = metadata.len();
+    args.min_size.is_none_or(|min| len >= min) && args.max_size.is_none_or(|max| len <= max)
 }
 
-fn can_be_media_file(path: &Path) -> bool {
+fn is_excluded_extension(path: &Path) -> bool {
     match path.extension() {
-        None => true,
-        Some(ext) => !matches!(
+        None => false,
+        Some(ext) => matches!(
             ext.to_string_lossy().to_lowercase().as_str(),
             "bat"
                 | "config"
@@ -225,34 +1035,169 @@ fn process_file(
     target: &Path,
     creation_date: &DateTime<Utc>,
 ) -> anyhow::Result<()> {
-    let target_subdir = creation_date.format(&args.target_dir_pattern).to_string();
-    let target_dir = target.join(target_subdir);
-    make_path(ctx, args, &target_dir)?;
+    let (target_dir_pattern, target_file_pattern) = args.patterns_for(path);
 
-    let mut target_filename = creation_date.format(&args.target_file_pattern).to_string();
+    let target_dir = if args.rename_only {
+        path.parent()
+            .expect("Cannot extract parent directory")
+            .to_path_buf()
+    } else if args.flat {
+        target.to_path_buf()
+    } else {
+        let target_subdir = creation_date.format(target_dir_pattern).to_string();
+        let target_dir = target.join(target_subdir);
+        make_path(ctx, args, &target_dir)?;
+        target_dir
+    };
+
+    let file_stem = creation_date.format(target_file_pattern).to_string();
+    let mut target_filename = file_stem.clone();
+    // name the file would have if the extension case was preserved
+    let mut original_case_filename = None;
     if let Some(extension) = path.extension() {
-        target_filename = format!("{target_filename}.{}", extension.to_string_lossy())
+        let original = format!("{file_stem}.{}", extension.to_string_lossy());
+        let extension = if args.preserve_extension_case {
+            extension.to_os_string()
+        } else {
+            extension.to_ascii_lowercase()
+        };
+        target_filename = format!("{file_stem}.{}", extension.to_string_lossy());
+        if original != target_filename {
+            original_case_filename = Some(original);
+        }
     }
 
-    copy_file(args, path, &target_dir, &target_filename)?;
+    let outcome = if args.rename_only {
+        rename_file(args, path, &target_dir, &target_filename)?
+    } else if let Some(duplicate) =
+        find_original_case_duplicate(args, path, &target_dir, original_case_filename)?
+    {
+        duplicate
+    } else {
+        copy_file(args, path, &target_dir, &target_filename)?
+    };
+    if let CopyOutcome::Transferred { path, sha256 } = outcome {
+        *ctx.dir_file_counts.entry(target_dir).or_default() += 1;
+        record_checksum(ctx, &path, sha256);
+    }
     Ok(())
 }
 
+/// Files synced before extensions were converted to lowercase keep the original case,
+/// so the original name is checked for a duplicate before copying the file again.
+fn find_original_case_duplicate(
+    args: &Args,
+    source: &Path,
+    target_dir: &Path,
+    original_case_filename: Option<String>,
+) -> anyhow::Result<Option<CopyOutcome>> {
+    let Some(filename) = original_case_filename else {
+        return Ok(None);
+    };
+    let target = target_dir.join(filename);
+    if !args.fs.exists(&target) {
+        return Ok(None);
+    }
+    if !is_duplicate(&args.fs.metadata(source)?, &args.fs.metadata(&target)?) {
+        return Ok(None);
+    }
+    debug!(
+        "Duplicate has been found. Source: [{}], Target: [{}]",
+        args.fmt_path(source),
+        args.fmt_path(&target)
+    );
+    args.stats.count_duplicate();
+    Ok(Some(CopyOutcome::Duplicate(target)))
+}
+
+/// Files with the same modification time or size are treated as the same file.
+fn is_duplicate(source: &Metadata, target: &Metadata) -> bool {
+    source.modified() == target.modified() || source.len() == target.len()
+}
+
+/// Remembers the checksum of a transferred file for [`write_checksums`].
+fn record_checksum(ctx: &mut AppContext, path: &Path, sha256: Option<String>) {
+    let Some(sha256) = sha256 else {
+        return;
+    };
+    let dir = path.parent().expect("Cannot extract parent directory");
+    let file_name = path
+        .file_name()
+        .expect("Cannot extract filename")
+        .to_string_lossy()
+        .to_string();
+    ctx.checksums
+        .entry(dir.to_path_buf())
+        .or_default()
+        .push((file_name, sha256));
+}
+
 fn process_unrecognized_file(ctx: &mut AppContext, args: &Args, path: &Path) -> anyhow::Result<()> {
     let file_name = path
         .file_name()
         .expect("Cannot extract filename")
         .to_string_lossy();
     make_path(ctx, args, &args.unrecognized)?;
-    copy_file(args, path, &args.unrecognized, &file_name)
+    if let CopyOutcome::Transferred { path, sha256 } =
+        copy_file(args, path, &args.unrecognized, &file_name)?
+    {
+        record_checksum(ctx, &path, sha256);
+    }
+    Ok(())
 }
 
+#[derive(Debug, Clone, PartialEq)]
+enum CopyOutcome {
+    /// The file has been copied or renamed to the given path.
+    /// The checksum is set if `--generate-checksums` is specified.
+    Transferred {
+        path: PathBuf,
+        sha256: Option<String>,
+    },
+    /// The file already exists at the given path.
+    Duplicate(PathBuf),
+    /// A different file exists at the given path and has been kept according to the conflict strategy.
+    Skipped(PathBuf),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Transfer {
+    Copy,
+    Rename,
+    Move,
+}
+
+/// Copies the file into the target directory, or moves it if `--move` is specified.
 fn copy_file(
     args: &Args,
     source: &Path,
     target_dir: &Path,
     target_filename: &str,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<CopyOutcome> {
+    let transfer = if args.move_files {
+        Transfer::Move
+    } else {
+        Transfer::Copy
+    };
+    transfer_file(args, source, target_dir, target_filename, transfer)
+}
+
+fn rename_file(
+    args: &Args,
+    source: &Path,
+    target_dir: &Path,
+    target_filename: &str,
+) -> anyhow::Result<CopyOutcome> {
+    transfer_file(args, source, target_dir, target_filename, Transfer::Rename)
+}
+
+fn transfer_file(
+    args: &Args,
+    source: &Path,
+    target_dir: &Path,
+    target_filename: &str,
+    transfer: Transfer,
+) -> anyhow::Result<CopyOutcome> {
     let source_metadata = args.fs.metadata(source)?;
 
     let (base_name, extension) = match target_filename.rfind('.') {
@@ -262,59 +1207,1539 @@ fn copy_file(
 
     let mut target = target_dir.join(target_filename);
     let mut index = 1;
+    let mut overwrite = false;
     while args.fs.exists(&target) {
+        if target == source {
+            args.stats.count_duplicate();
+            return Ok(CopyOutcome::Duplicate(target));
+        }
         let target_metadata = args.fs.metadata(&target)?;
 
-        if source_metadata.modified() == target_metadata.modified()
-            || source_metadata.len() == target_metadata.len()
-        {
-            println!(
+        if is_duplicate(&source_metadata, &target_metadata) {
+            debug!(
                 "Duplicate has been found. Source: [{}], Target: [{}]",
-                source.display(),
-                target.display()
+                args.fmt_path(source),
+                args.fmt_path(&target)
             );
-            return Ok(());
+            args.stats.count_duplicate();
+            return Ok(CopyOutcome::Duplicate(target));
         }
 
-        let new_filename = format!("{base_name}_{index}{extension}");
-        target = target_dir.join(new_filename);
-        index += 1;
+        let resolution = match &args.prompt {
+            // once renaming has been chosen, the next free name is used without asking again
+            Some(prompt) if index == 1 => {
+                match prompt.resolve_conflict(
+                    source,
+                    &source_metadata,
+                    &target,
+                    &target_metadata,
+                )? {
+                    ConflictChoice::Skip => ConflictResolution::Skip,
+                    ConflictChoice::Overwrite => ConflictResolution::Overwrite,
+                    ConflictChoice::Rename => ConflictResolution::Rename,
+                    ConflictChoice::Quit => bail!("The sync has been stopped by the user"),
+                }
+            }
+            Some(_) => ConflictResolution::Rename,
+            None => resolve_conflict(args.conflict_strategy, &source_metadata, &target_metadata),
+        };
+        match resolution {
+            ConflictResolution::Rename => {
+                let new_filename = format!("{base_name}_{index}{extension}");
+                target = target_dir.join(new_filename);
+                index += 1;
+            }
+            ConflictResolution::Overwrite => {
+                debug!(
+                    "Replacing [{}] with [{}]",
+                    args.fmt_path(&target),
+                    args.fmt_path(source)
+                );
+                overwrite = true;
+                break;
+            }
+            ConflictResolution::Skip => {
+                debug!(
+                    "Keeping the existing file. Source: [{}], Target: [{}]",
+                    args.fmt_path(source),
+                    args.fmt_path(&target)
+                );
+                args.stats.count_skip();
+                return Ok(CopyOutcome::Skipped(target));
+            }
+        }
     }
 
-    args.fs.copy(source, &target)?;
+    if let (Transfer::Copy | Transfer::Move, Some(min_free)) = (&transfer, args.min_free_space) {
+        if !args.dry_run {
+            check_disk_space(args, target_dir, source_metadata.len(), min_free)?;
+        }
+    }
+    // an existing target is replaced only once the new file is complete,
+    // so it is kept intact if the transfer fails or hits the copy limit
+    let destination = if overwrite {
+        replacement_path(&target)
+    } else {
+        target.clone()
+    };
+    let mut sha256 = None;
+    match transfer {
+        Transfer::Copy if args.copy_symlinks_as_symlinks && source_metadata.is_symlink() => {
+            let original = args.fs.canonicalize(source)?;
+            args.fs.symlink(&original, &destination)?;
+        }
+        Transfer::Copy if args.generate_checksums => {
+            sha256 = Some(args.fs.copy_with_sha256(source, &destination)?.1);
+        }
+        Transfer::Copy => {
+            args.fs.copy(source, &destination)?;
+        }
+        Transfer::Rename => args.fs.rename(source, &destination)?,
+        Transfer::Move => args.fs.move_file(source, &destination)?,
+    }
+    if overwrite {
+        args.fs.replace(&destination, &target)?;
+    }
+    if index > 1 {
+        args.stats.count_collision();
+    }
+    // renamed, moved and symlinked files are hashed in place, as no data has been copied
+    if args.generate_checksums && sha256.is_none() {
+        sha256 = Some(args.fs.sha256(&target)?);
+    }
+    Ok(CopyOutcome::Transferred {
+        path: target,
+        sha256,
+    })
+}
+
+/// Temporary name the source is transferred to before it replaces an existing target.
+fn replacement_path(target: &Path) -> PathBuf {
+    let mut path = target.as_os_str().to_os_string();
+    path.push(".new");
+    path.into()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ConflictResolution {
+    /// Keep both files by transferring the source under a new name.
+    Rename,
+    /// Replace the existing target with the source.
+    Overwrite,
+    /// Keep the existing target.
+    Skip,
+}
+
+/// Decides what to do with a source file whose target name is taken by a different file.
+fn resolve_conflict(
+    strategy: ConflictStrategy,
+    source: &Metadata,
+    target: &Metadata,
+) -> ConflictResolution {
+    let source_wins = match strategy {
+        ConflictStrategy::Rename => return ConflictResolution::Rename,
+        ConflictStrategy::Skip => return ConflictResolution::Skip,
+        ConflictStrategy::KeepOlder => source.modified() < target.modified(),
+        ConflictStrategy::KeepNewer => source.modified() > target.modified(),
+        ConflictStrategy::KeepLarger => source.len() > target.len(),
+    };
+    if source_wins {
+        ConflictResolution::Overwrite
+    } else {
+        ConflictResolution::Skip
+    }
+}
+
+fn check_disk_space(
+    args: &Args,
+    target_dir: &Path,
+    required: u64,
+    min_free: u64,
+) -> anyhow::Result<()> {
+    let available = args.space.available_space(target_dir).with_context(|| {
+        format!(
+            "Failed to get available disk space for [{}]",
+            args.fmt_path(target_dir)
+        )
+    })?;
+    if available.saturating_sub(required) < min_free {
+        bail!(
+            "Disk space guard triggered: copying {required} bytes to [{}] would leave less than {min_free} bytes free (available: {available})",
+            args.fmt_path(target_dir)
+        );
+    }
     Ok(())
 }
 
-fn log_unknown_files(args: &Args, unknown_files: &Vec<PathBuf>) -> io::Result<()> {
-    let log_path = args.unrecognized.join("unknown_files.log");
-    let mut log_file = File::create(log_path)?;
-    for file in unknown_files {
-        writeln!(log_file, "{}", file.display())?;
+/// Writes `SHA256SUMS` files in the `sha256sum` format, keeping entries
+/// from previous runs for files that were not synced this time.
+fn write_checksums(ctx: &AppContext, args: &Args) -> anyhow::Result<()> {
+    for (dir, checksums) in &ctx.checksums {
+        let path = dir.join(CHECKSUM_FILE_NAME);
+        let mut entries = BTreeMap::new();
+        if args.fs.exists(&path) {
+            let existing = args.fs.read_to_string(&path)?;
+            for line in existing.lines() {
+                if let Some((hash, file_name)) = line.split_once("  ") {
+                    entries.insert(file_name.to_string(), hash.to_string());
+                }
+            }
+        }
+        for (file_name, hash) in checksums {
+            entries.insert(file_name.clone(), hash.clone());
+        }
+
+        let mut content = String::new();
+        for (file_name, hash) in entries {
+            writeln!(content, "{hash}  {file_name}")?;
+        }
+        args.fs.write_bytes(&path, content.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn log_unknown_files(args: &Args, unknown_files: &[UnrecognizedFile]) -> anyhow::Result<()> {
+    match args.unknown_log_format {
+        UnknownLogFormat::Text => {
+            let log_path = args.unrecognized.join("unknown_files.log");
+            let mut log_file = File::create(log_path)?;
+            for file in unknown_files {
+                writeln!(log_file, "{}", args.fmt_path(&file.path))?;
+            }
+        }
+        UnknownLogFormat::Json => {
+            let log_path = args.unrecognized.join("unknown_files.json");
+            let entries: Vec<UnrecognizedFileEntry> = unknown_files
+                .iter()
+                .map(|file| UnrecognizedFileEntry::new(file, args.output_encoding))
+                .collect();
+            let json = serde_json::to_string_pretty(&entries)
+                .with_context(|| "Failed to serialize unknown files")?;
+            std::fs::write(&log_path, json)
+                .with_context(|| format!("Failed to write [{}]", args.fmt_path(&log_path)))?;
+        }
     }
     Ok(())
 }
 
-fn print_unknown_files(unknown_files: &Vec<PathBuf>) {
+fn print_unknown_files(unknown_files: &[UnrecognizedFile], encoding: OutputEncoding) {
     if unknown_files.is_empty() {
         return;
     }
-    println!("Unrecognized files:");
+    info!("Unrecognized files:");
     for file in unknown_files {
-        println!("{}", file.display());
+        info!("{}", PathFormatter::new(&file.path, encoding));
     }
 }
 
-fn print_dry_run(objects: &fs::dry::ObjectMap) {
+fn print_dir_stats(ctx: &AppContext, encoding: OutputEncoding) {
+    const TOP_DIRS_COUNT: usize = 10;
+
+    let mut counts: Vec<(&PathBuf, &u64)> = ctx.dir_file_counts.iter().collect();
+    counts.sort_by(|(path1, count1), (path2, count2)| count2.cmp(count1).then(path1.cmp(path2)));
+    info!("Top directories by file count:");
+    for (path, count) in counts.iter().take(TOP_DIRS_COUNT) {
+        info!("{:<120} {:>10}", PathFormatter::new(path, encoding), count);
+    }
+    info!("Output directories: {}", counts.len());
+}
+
+fn print_dry_run(objects: &fs::dry::ObjectMap, encoding: OutputEncoding) {
     let mut sorted: Vec<(&PathBuf, &(Metadata, Option<PathBuf>))> = objects.iter().collect();
-    sorted.sort_by(|(path1, _), (path2, _)| path1.cmp(path2));
+    sorted.sort_by_key(|(path, _)| *path);
     for (path, (meta, source)) in sorted {
         if meta.is_dir() {
-            println!("{}\\", path.display());
+            info!("{}\\", PathFormatter::new(path, encoding));
         } else {
-            println!("{:<120} {:>10}", path.display(), meta.len());
-            if let Some(source) = source {
-                println!("╰── {}", source.display())
+            info!(
+                "{:<120} {:>10}",
+                PathFormatter::new(path, encoding),
+                meta.len()
+            );
+            match source {
+                Some(source) if meta.is_symlink() => {
+                    info!("╰─> {}", PathFormatter::new(source, encoding))
+                }
+                Some(source) => info!("╰── {}", PathFormatter::new(source, encoding)),
+                None => {}
+            }
+        }
+    }
+}
+
+fn print_dry_run_renames(objects: &ObjectMap, encoding: OutputEncoding) {
+    let mut renames: Vec<(&PathBuf, &PathBuf)> = objects
+        .iter()
+        .filter_map(|(path, (_, source))| source.as_ref().map(|source| (source, path)))
+        .collect();
+    renames.sort();
+    for (source, path) in renames {
+        info!(
+            "{:<80} -> {}",
+            PathFormatter::new(source, encoding),
+            PathFormatter::new(path, encoding)
+        );
+    }
+}
+
+fn load_dry_run_results(path: &Path, encoding: OutputEncoding) -> anyhow::Result<ObjectMap> {
+    let saved = std::fs::read_to_string(path).with_context(|| {
+        format!(
+            "Failed to read dry run results from [{}]",
+            PathFormatter::new(path, encoding)
+        )
+    })?;
+    ObjectMap::from_json(&saved)
+}
+
+fn print_dry_run_diff(before: &ObjectMap, after: &ObjectMap, encoding: OutputEncoding) {
+    info!("Dry run diff:");
+    for entry in diff_object_maps(before, after) {
+        match entry {
+            DiffEntry::Added(path) => info!("+ {}", PathFormatter::new(&path, encoding)),
+            DiffEntry::Removed(path) => info!("- {}", PathFormatter::new(&path, encoding)),
+            DiffEntry::Changed(path) => info!("~ {}", PathFormatter::new(&path, encoding)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::DirIterator;
+    use crate::test_util::{date, std_args, FixedSpace, MockPrompt, TestEnv};
+    use std::cell::Cell;
+    use tracing_test::traced_test;
+
+    #[test]
+    fn unknown_specifier_is_rejected() {
+        assert!(Args::validate_dir_pattern("%Q/%Y").is_err());
+        assert!(Args::validate_file_pattern("%Q").is_err());
+    }
+
+    #[test]
+    fn escaped_percent_is_accepted() {
+        assert!(Args::validate_dir_pattern("%Y%%%m").is_ok());
+        assert!(Args::validate_file_pattern("%Y%%%m").is_ok());
+    }
+
+    #[test]
+    fn file_pattern_with_separators_is_rejected() {
+        assert!(Args::validate_dir_pattern("%Y/%m/%d").is_ok());
+        assert!(Args::validate_file_pattern("%Y/%m/%d").is_err());
+        assert!(Args::validate_file_pattern("%Y\\%m").is_err());
+    }
+
+    #[test]
+    fn overwrite_replaces_the_target() {
+        let mut env = TestEnv::new(&[
+            "--date-source",
+            "modified",
+            "--conflict-strategy",
+            "keep-newer",
+        ]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00.5Z"));
+        env.add_file(
+            "/dst/2024/01/01/2024-01-01T000000.jpg",
+            5,
+            date("2024-01-01T00:00:00Z"),
+        );
+        env.sync();
+
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/01/2024-01-01T000000.jpg")]
+        );
+        assert_eq!(
+            env.metadata("/dst/2024/01/01/2024-01-01T000000.jpg").len(),
+            10
+        );
+        assert_eq!(env.stats.copied_count(), 1);
+    }
+
+    #[test]
+    fn overwrite_keeps_the_target_if_the_copy_fails() {
+        let mut env = TestEnv::new(&[
+            "--date-source",
+            "modified",
+            "--conflict-strategy",
+            "keep-newer",
+            "--max-files",
+            "0",
+        ]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00.5Z"));
+        env.add_file(
+            "/dst/2024/01/01/2024-01-01T000000.jpg",
+            5,
+            date("2024-01-01T00:00:00Z"),
+        );
+        let result = env.sync();
+
+        assert_eq!(result.limit_reached, [PathBuf::from("/src/a.jpg")]);
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/01/2024-01-01T000000.jpg")]
+        );
+        assert_eq!(
+            env.metadata("/dst/2024/01/01/2024-01-01T000000.jpg").len(),
+            5
+        );
+    }
+
+    #[test]
+    fn resolve_conflict_matrix() {
+        use ConflictResolution::{Overwrite, Rename, Skip};
+        use ConflictStrategy::{KeepLarger, KeepNewer, KeepOlder};
+
+        let old = date("2024-01-01T00:00:00Z");
+        let new = date("2024-06-01T00:00:00Z");
+        // (source modified, source len, target modified, target len)
+        let cases = [
+            (new, 10, old, 5),  // source newer and larger
+            (new, 5, old, 10),  // source newer and smaller
+            (old, 10, new, 5),  // source older and larger
+            (old, 5, new, 10),  // source older and smaller
+            (old, 10, old, 5),  // same mtime, source larger
+            (new, 10, new, 10), // same mtime and size
+        ];
+        let expected = [
+            (KeepNewer, [Overwrite, Overwrite, Skip, Skip, Skip, Skip]),
+            (KeepOlder, [Skip, Skip, Overwrite, Overwrite, Skip, Skip]),
+            (
+                KeepLarger,
+                [Overwrite, Skip, Overwrite, Skip, Overwrite, Skip],
+            ),
+        ];
+
+        for (source_modified, source_len, target_modified, target_len) in cases {
+            let source = Metadata::dummy_file(source_len).with_modified(source_modified);
+            let target = Metadata::dummy_file(target_len).with_modified(target_modified);
+            assert_eq!(
+                resolve_conflict(ConflictStrategy::Rename, &source, &target),
+                Rename
+            );
+            assert_eq!(
+                resolve_conflict(ConflictStrategy::Skip, &source, &target),
+                Skip
+            );
+        }
+        for (strategy, resolutions) in expected {
+            for ((source_modified, source_len, target_modified, target_len), resolution) in
+                cases.into_iter().zip(resolutions)
+            {
+                let source = Metadata::dummy_file(source_len).with_modified(source_modified);
+                let target = Metadata::dummy_file(target_len).with_modified(target_modified);
+                assert_eq!(
+                    resolve_conflict(strategy, &source, &target),
+                    resolution,
+                    "{strategy:?}: source ({source_modified:?}, {source_len}), target ({target_modified:?}, {target_len})"
+                );
             }
         }
     }
+
+    fn sha256_hex(data: &[u8]) -> String {
+        use sha2::{Digest, Sha256};
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    #[test]
+    fn checksums_use_the_sha256sum_format() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.jpg"), b"first").unwrap();
+        std::fs::write(source.path().join("b.jpg"), b"second file").unwrap();
+        let args = std_args(
+            source.path(),
+            target.path(),
+            &[
+                "--date-source",
+                "modified",
+                "--flat",
+                "--generate-checksums",
+            ],
+        );
+        let mut ctx = AppContext::default();
+        sync_media(&mut ctx, &args).unwrap();
+        write_checksums(&ctx, &args).unwrap();
+
+        let sums = std::fs::read_to_string(target.path().join(CHECKSUM_FILE_NAME)).unwrap();
+        let mut hashes: Vec<String> = Vec::new();
+        for line in sums.lines() {
+            let (hash, file_name) = line.split_once("  ").unwrap();
+            assert_eq!(hash.len(), 64);
+            assert!(hash
+                .bytes()
+                .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()));
+            let content = std::fs::read(target.path().join(file_name)).unwrap();
+            assert_eq!(hash, sha256_hex(&content));
+            hashes.push(hash.to_string());
+        }
+        hashes.sort();
+        let mut expected = vec![sha256_hex(b"first"), sha256_hex(b"second file")];
+        expected.sort();
+        assert_eq!(hashes, expected);
+    }
+
+    #[test]
+    fn checksums_keep_the_existing_entries() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.jpg"), b"first").unwrap();
+        let old_hash = sha256_hex(b"old");
+        std::fs::write(
+            target.path().join(CHECKSUM_FILE_NAME),
+            format!("{old_hash}  0000-old.jpg\n"),
+        )
+        .unwrap();
+        let args = std_args(
+            source.path(),
+            target.path(),
+            &[
+                "--date-source",
+                "modified",
+                "--flat",
+                "--generate-checksums",
+            ],
+        );
+        let mut ctx = AppContext::default();
+        sync_media(&mut ctx, &args).unwrap();
+        write_checksums(&ctx, &args).unwrap();
+
+        let sums = std::fs::read_to_string(target.path().join(CHECKSUM_FILE_NAME)).unwrap();
+        let lines: Vec<&str> = sums.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], format!("{old_hash}  0000-old.jpg"));
+        assert!(lines[1].starts_with(&sha256_hex(b"first")));
+    }
+
+    #[test]
+    fn checksums_skip_duplicates() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        std::fs::write(source.path().join("a.jpg"), b"AAAA").unwrap();
+        let args = std_args(
+            source.path(),
+            target.path(),
+            &[
+                "--date-source",
+                "modified",
+                "--flat",
+                "--generate-checksums",
+            ],
+        );
+        let modified = std::fs::metadata(source.path().join("a.jpg"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        let name = DateTime::<Utc>::from(modified)
+            .format("%Y-%m-%dT%H%M%S.jpg")
+            .to_string();
+        // same size, different content
+        std::fs::write(target.path().join(name), b"BBBB").unwrap();
+
+        let mut ctx = AppContext::default();
+        sync_media(&mut ctx, &args).unwrap();
+        write_checksums(&ctx, &args).unwrap();
+
+        assert_eq!(args.stats.skipped_duplicates(), 1);
+        assert!(!target.path().join(CHECKSUM_FILE_NAME).exists());
+    }
+
+    #[test]
+    fn unknown_files_json_round_trip() {
+        let files = [
+            UnrecognizedFile {
+                path: PathBuf::from("/src/notes.txt"),
+                reason: UnrecognizedReason::ExtensionExcluded,
+                source_root: PathBuf::from("/src"),
+            },
+            UnrecognizedFile {
+                path: PathBuf::from("/src/broken.jpg"),
+                reason: UnrecognizedReason::ExifFailed,
+                source_root: PathBuf::from("/src"),
+            },
+        ];
+        let entries: Vec<UnrecognizedFileEntry> = files
+            .iter()
+            .map(|file| UnrecognizedFileEntry::new(file, OutputEncoding::Utf8Lossy))
+            .collect();
+        let json = serde_json::to_string_pretty(&entries).unwrap();
+        assert!(json.contains(r#""reason": "extension_excluded""#));
+        assert!(json.contains(r#""reason": "exif_failed""#));
+
+        let parsed: Vec<UnrecognizedFileEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, entries);
+        assert_eq!(parsed[1].path, "/src/broken.jpg");
+        assert_eq!(parsed[1].source_root, "/src");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unknown_files_json_with_non_utf8_path() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let file = UnrecognizedFile {
+            path: PathBuf::from(std::ffi::OsStr::from_bytes(b"/src/bad\xff.txt")),
+            reason: UnrecognizedReason::ExtensionExcluded,
+            source_root: PathBuf::from("/src"),
+        };
+        let entry = UnrecognizedFileEntry::new(&file, OutputEncoding::PercentEncoded);
+        let json = serde_json::to_string(&[entry]).unwrap();
+        let parsed: Vec<UnrecognizedFileEntry> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed[0].path, "/src/bad%FF.txt");
+    }
+
+    #[test]
+    fn plan_sync_does_not_write() {
+        let env = TestEnv::new(&["--two-pass", "--date-source", "modified", "--size-min", "5"]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file("/src/nested/b.mp4", 20, date("2024-02-01T00:00:00Z"));
+        env.add_file("/src/small.jpg", 1, date("2024-03-01T00:00:00Z"));
+        env.add_file("/src/notes.txt", 10, date("2024-04-01T00:00:00Z"));
+        let before = env.objects.borrow().clone();
+
+        let plan = plan_sync(&env.args).unwrap();
+
+        assert_eq!(**env.objects.borrow(), before);
+        let mut planned: Vec<(&Path, u64)> = plan
+            .files
+            .iter()
+            .map(|file| (file.source.as_path(), file.len))
+            .collect();
+        planned.sort();
+        assert_eq!(
+            planned,
+            [
+                (Path::new("/src/a.jpg"), 10),
+                (Path::new("/src/nested/b.mp4"), 20)
+            ]
+        );
+        assert!(plan.files.iter().all(|file| file.creation_date.is_some()));
+        assert_eq!(plan.total_size(), 30);
+        assert_eq!(
+            plan.result.size_filtered_files,
+            [PathBuf::from("/src/small.jpg")]
+        );
+        assert_eq!(plan.result.extension_excluded_count, 1);
+    }
+
+    #[test]
+    fn two_pass_sync_can_be_cancelled() {
+        let mut env = TestEnv::new(&["--two-pass", "--date-source", "modified"]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.args.prompt = Some(Box::new(MockPrompt {
+            choice: ConflictChoice::Quit,
+            confirm: false,
+        }));
+
+        let err = sync_media(&mut env.ctx, &env.args).unwrap_err();
+        assert_eq!(err.to_string(), "The sync has been cancelled by the user");
+        assert!(!env.args.fs.exists(Path::new("/dst")));
+
+        env.args.prompt = Some(Box::new(MockPrompt {
+            choice: ConflictChoice::Quit,
+            confirm: true,
+        }));
+        env.sync();
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/01/2024-01-01T000000.jpg")]
+        );
+    }
+
+    #[test]
+    fn disk_space_guard() {
+        let mut env = TestEnv::new(&["--min-free-space", "100"]);
+        env.args.space = Box::new(FixedSpace(Some(150)));
+        assert!(check_disk_space(&env.args, Path::new("/dst"), 50, 100).is_ok());
+        let err = check_disk_space(&env.args, Path::new("/dst"), 51, 100).unwrap_err();
+        assert!(err.to_string().starts_with("Disk space guard triggered"));
+
+        env.args.space = Box::new(FixedSpace(None));
+        let err = check_disk_space(&env.args, Path::new("/dst"), 0, 100).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to get available disk space for [/dst]"
+        );
+    }
+
+    #[test]
+    fn disk_space_guard_stops_the_sync() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--min-free-space", "100"]);
+        env.add_file("/src/a.jpg", 60, date("2024-01-01T00:00:00Z"));
+        env.args.space = Box::new(FixedSpace(Some(150)));
+
+        let err = sync_media(&mut env.ctx, &env.args).unwrap_err();
+        assert!(format!("{err:#}").contains("Disk space guard triggered"));
+        assert!(env.files("/dst").is_empty());
+
+        env.args.space = Box::new(FixedSpace(Some(160)));
+        env.sync();
+        assert_eq!(env.files("/dst").len(), 1);
+    }
+
+    #[test]
+    fn interactive_skip_keeps_the_target() {
+        let mut env = TestEnv::new(&["--date-source", "modified"]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00.5Z"));
+        env.add_file(
+            "/dst/2024/01/01/2024-01-01T000000.jpg",
+            5,
+            date("2024-01-01T00:00:00Z"),
+        );
+        env.args.prompt = Some(Box::new(MockPrompt {
+            choice: ConflictChoice::Skip,
+            confirm: true,
+        }));
+        env.sync();
+
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/01/2024-01-01T000000.jpg")]
+        );
+        let target = env.metadata("/dst/2024/01/01/2024-01-01T000000.jpg");
+        assert_eq!(target.len(), 5);
+        assert_eq!(target.modified(), date("2024-01-01T00:00:00Z"));
+        assert_eq!(env.stats.skipped_count(), 1);
+        assert_eq!(env.stats.copied_count(), 0);
+    }
+
+    /// Checks that an event with the level ends with the message.
+    fn logged(lines: &[&str], level: &str, message: &str) -> Result<(), String> {
+        let level = format!(" {level} ");
+        match lines
+            .iter()
+            .any(|line| line.contains(&level) && line.ends_with(message))
+        {
+            true => Ok(()),
+            false => Err(format!("no{level}event [{message}]")),
+        }
+    }
+
+    #[traced_test]
+    #[test]
+    fn duplicates_are_logged_at_debug_level() {
+        let mut env = TestEnv::new(&["--date-source", "modified"]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file(
+            "/dst/2024/01/01/2024-01-01T000000.jpg",
+            10,
+            date("2024-01-01T00:00:00Z"),
+        );
+        env.sync();
+
+        logs_assert(|lines| {
+            logged(
+                lines,
+                "DEBUG",
+                "Duplicate has been found. Source: [/src/a.jpg], Target: [/dst/2024/01/01/2024-01-01T000000.jpg]",
+            )
+        });
+    }
+
+    #[traced_test]
+    #[test]
+    fn summary_is_logged_at_info_level() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--perf"]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        let result = env.sync();
+        print_summary(&env.args, &result);
+
+        logs_assert(|lines| logged(lines, "INFO", "Copied files: 1"));
+        logs_assert(|lines| logged(lines, "INFO", "Copied data size: 10"));
+        assert!(logs_contain("Copy duration p50:"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn missing_dry_run_results_are_percent_encoded() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir
+            .path()
+            .join(std::ffi::OsStr::from_bytes(b"before\xff.json"));
+        let err = load_dry_run_results(&path, OutputEncoding::PercentEncoded).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Failed to read dry run results from [{}/before%FF.json]",
+                dir.path().display()
+            )
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_file_names_are_percent_encoded() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"100%\xff.txt");
+        std::fs::write(source.path().join(name), b"text").unwrap();
+        let args = std_args(
+            source.path(),
+            target.path(),
+            &[
+                "--skip-unrecognized",
+                "--output-encoding",
+                "percent-encoded",
+            ],
+        );
+        let mut ctx = AppContext::default();
+        let result = sync_media(&mut ctx, &args).unwrap();
+
+        let [file] = result.unrecognized_files.as_slice() else {
+            panic!(
+                "unexpected unrecognized files: {:?}",
+                result.unrecognized_files
+            );
+        };
+        assert_eq!(
+            args.fmt_path(&file.path).to_string(),
+            format!("{}/100%25%FF.txt", source.path().display())
+        );
+    }
+
+    #[test]
+    fn summary_counts_every_file_once() {
+        let mut env = TestEnv::new(&[
+            "--date-source",
+            "modified",
+            "--conflict-strategy",
+            "skip",
+            "--source-exclude",
+            "*.tmp",
+            "--size-max",
+            "100",
+        ]);
+        let day = date("2024-01-01T00:00:00Z");
+        env.add_file("/dst/2024/01/01/2024-01-01T000000.jpg", 10, day);
+        env.add_file("/dst/2024/01/01/2024-01-01T000000.png", 10, day);
+        env.add_file("/src/duplicate.jpg", 10, day);
+        env.add_file("/src/conflict.png", 20, date("2024-01-01T00:00:00.5Z"));
+        env.add_file("/src/excluded.tmp", 10, day);
+        env.add_file("/src/large.jpg", 1000, day);
+        env.add_file("/src/notes.txt", 10, day);
+        for i in 0..5 {
+            env.add_file(
+                &format!("/src/{i}.mp4"),
+                10,
+                date(&format!("2024-02-0{}T00:00:00Z", i + 1)),
+            );
+        }
+        let result = env.sync();
+
+        let stats = &env.stats;
+        assert_eq!(stats.copied_count(), 5);
+        assert_eq!(stats.skipped_duplicates(), 1);
+        assert_eq!(stats.skipped_count(), 1);
+        assert_eq!(stats.skipped_by_filter(), 3);
+        assert_eq!(result.size_filtered_files.len(), 1);
+        assert_eq!(result.extension_excluded_count, 1);
+        assert_eq!(result.total_files(stats), 10);
+    }
+
+    #[test]
+    fn summary_counts_files_over_the_limit() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--max-files", "3"]);
+        for i in 0..5 {
+            env.add_file(
+                &format!("/src/{i}.jpg"),
+                10,
+                date(&format!("2024-02-0{}T00:00:00Z", i + 1)),
+            );
+        }
+        let result = env.sync();
+        assert_eq!(env.stats.copied_count(), 3);
+        assert_eq!(result.limit_reached.len(), 2);
+        assert_eq!(result.total_files(&env.stats), 5);
+    }
+
+    #[test]
+    fn unrecognized_copies_are_counted_once() {
+        let mut env = TestEnv::new(&[]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file("/src/b.jpg", 10, date("2024-01-01T00:00:00Z"));
+        let result = env.sync();
+        assert_eq!(env.stats.copied_count(), 2);
+        assert_eq!(result.exif_failed_count, 0);
+        assert_eq!(result.exif_failed_unrecognized_count, 2);
+        assert_eq!(result.unrecognized_files.len(), 2);
+        assert_eq!(result.total_files(&env.stats), 2);
+
+        let mut env = TestEnv::new(&["--skip-unrecognized"]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        let result = env.sync();
+        assert_eq!(env.stats.copied_count(), 0);
+        assert_eq!(result.exif_failed_count, 1);
+        assert_eq!(result.exif_failed_unrecognized_count, 0);
+        assert_eq!(result.total_files(&env.stats), 1);
+    }
+
+    #[test]
+    fn extensions_are_converted_to_lowercase() {
+        let mut env = TestEnv::new(&["--date-source", "modified"]);
+        env.add_file("/src/IMG_0001.HEIC", 10, date("2024-01-01T00:00:00Z"));
+        env.sync();
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/01/2024-01-01T000000.heic")]
+        );
+
+        let mut env = TestEnv::new(&["--date-source", "modified", "--preserve-extension-case"]);
+        env.add_file("/src/IMG_0001.HEIC", 10, date("2024-01-01T00:00:00Z"));
+        env.sync();
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/01/2024-01-01T000000.HEIC")]
+        );
+    }
+
+    #[test]
+    fn original_case_target_is_a_duplicate() {
+        let mut env = TestEnv::new(&["--date-source", "modified"]);
+        env.add_file("/src/IMG_0001.JPG", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file(
+            "/dst/2024/01/01/2024-01-01T000000.JPG",
+            10,
+            date("2024-01-01T00:00:00Z"),
+        );
+        env.sync();
+
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/01/2024-01-01T000000.JPG")]
+        );
+        assert_eq!(env.stats.skipped_duplicates(), 1);
+        assert_eq!(env.stats.copied_count(), 0);
+    }
+
+    #[test]
+    fn original_case_target_with_different_content_is_kept() {
+        let mut env = TestEnv::new(&["--date-source", "modified"]);
+        env.add_file("/src/IMG_0001.JPG", 10, date("2024-01-01T00:00:00.5Z"));
+        env.add_file(
+            "/dst/2024/01/01/2024-01-01T000000.JPG",
+            5,
+            date("2024-01-01T00:00:00Z"),
+        );
+        env.sync();
+
+        assert_eq!(
+            env.files("/dst"),
+            [
+                PathBuf::from("/dst/2024/01/01/2024-01-01T000000.JPG"),
+                PathBuf::from("/dst/2024/01/01/2024-01-01T000000.jpg")
+            ]
+        );
+        assert_eq!(env.stats.copied_count(), 1);
+    }
+
+    #[test]
+    fn source_filter_selects_files() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--source-filter", "IMG_*.jpg"]);
+        env.add_file("/src/IMG_0001.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file("/src/VID_0001.mp4", 10, date("2024-01-02T00:00:00Z"));
+        let result = env.sync();
+
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/01/2024-01-01T000000.jpg")]
+        );
+        assert!(result.unrecognized_files.is_empty());
+        assert_eq!(env.stats.skipped_by_filter(), 1);
+    }
+
+    #[test]
+    fn source_exclude_skips_files_silently() {
+        let mut env = TestEnv::new(&[
+            "--date-source",
+            "modified",
+            "--source-exclude",
+            "*.txt",
+            "--source-exclude",
+            "VID_*",
+        ]);
+        env.add_file("/src/IMG_0001.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file("/src/VID_0001.mp4", 10, date("2024-01-02T00:00:00Z"));
+        env.add_file("/src/notes.txt", 10, date("2024-01-03T00:00:00Z"));
+        let result = env.sync();
+
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/01/2024-01-01T000000.jpg")]
+        );
+        assert!(result.unrecognized_files.is_empty());
+        assert_eq!(result.extension_excluded_count, 0);
+        assert_eq!(env.stats.skipped_by_filter(), 2);
+    }
+
+    #[test]
+    fn flat_puts_files_into_the_target_root() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--flat"]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file("/src/b.jpg", 20, date("2023-05-06T07:08:09Z"));
+        env.add_file("/src/nested/c.jpg", 30, date("2023-05-06T07:08:09.5Z"));
+        env.add_file("/src/nested/d.jpg", 40, date("2023-05-06T07:08:09.7Z"));
+        env.sync();
+
+        assert_eq!(
+            env.files("/dst"),
+            [
+                PathBuf::from("/dst/2023-05-06T070809.jpg"),
+                PathBuf::from("/dst/2023-05-06T070809_1.jpg"),
+                PathBuf::from("/dst/2023-05-06T070809_2.jpg"),
+                PathBuf::from("/dst/2024-01-01T000000.jpg"),
+            ]
+        );
+        assert_eq!(env.stats.collision_count(), 2);
+    }
+
+    #[test]
+    fn collisions_are_counted_once_per_file() {
+        let mut env = TestEnv::new(&["--date-source", "modified"]);
+        env.add_file("/src/a.jpg", 30, date("2024-01-01T00:00:00.5Z"));
+        env.add_file(
+            "/dst/2024/01/01/2024-01-01T000000.jpg",
+            10,
+            date("2024-01-01T00:00:00Z"),
+        );
+        env.add_file(
+            "/dst/2024/01/01/2024-01-01T000000_1.jpg",
+            20,
+            date("2024-01-01T00:00:00.2Z"),
+        );
+        env.sync();
+
+        assert!(env
+            .files("/dst")
+            .contains(&PathBuf::from("/dst/2024/01/01/2024-01-01T000000_2.jpg")));
+        assert_eq!(env.stats.collision_count(), 1);
+        assert_eq!(env.stats.copied_count(), 1);
+    }
+
+    #[test]
+    fn size_filters_skip_files() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--size-min", "1024"]);
+        env.add_file("/src/thumbnail.jpg", 100, date("2024-01-01T00:00:00Z"));
+        env.add_file("/src/photo.jpg", 2048, date("2024-01-02T00:00:00Z"));
+        let result = env.sync();
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/02/2024-01-02T000000.jpg")]
+        );
+        assert_eq!(
+            result.size_filtered_files,
+            [PathBuf::from("/src/thumbnail.jpg")]
+        );
+        assert!(result.unrecognized_files.is_empty());
+
+        let mut env = TestEnv::new(&["--date-source", "modified", "--size-max", "1073741824"]);
+        env.add_file("/src/raw.nef", 10_000_000_000, date("2024-01-01T00:00:00Z"));
+        env.add_file(
+            "/src/photo.jpg",
+            1_073_741_824,
+            date("2024-01-02T00:00:00Z"),
+        );
+        let result = env.sync();
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/02/2024-01-02T000000.jpg")]
+        );
+        assert_eq!(result.size_filtered_files, [PathBuf::from("/src/raw.nef")]);
+        assert_eq!(env.stats.skipped_by_filter(), 1);
+        assert_eq!(result.total_files(&env.stats), 2);
+    }
+
+    #[test]
+    fn skip_unrecognized_creates_no_directories() {
+        let mut env = TestEnv::new(&["--skip-unrecognized"]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file("/src/nested/b.mp4", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file("/src/notes.txt", 10, date("2024-01-01T00:00:00Z"));
+        let result = env.sync();
+
+        let entries: Vec<PathBuf> = env
+            .args
+            .fs
+            .list_dir(Path::new("/dst"), &HashSet::new())
+            .unwrap()
+            .map(|entry| entry.unwrap().path)
+            .collect();
+        assert_eq!(entries, [PathBuf::from("/dst")]);
+        assert_eq!(result.unrecognized_files.len(), 3);
+        assert_eq!(result.exif_failed_count, 2);
+        assert_eq!(result.extension_excluded_count, 1);
+    }
+
+    #[test]
+    fn ext_pattern_overrides_the_default_patterns() {
+        let mut env = TestEnv::new(&[
+            "--date-source",
+            "modified",
+            "--ext-pattern",
+            "MP4=videos/%Y/%m:%Y%m%d_%H%M%S",
+        ]);
+        env.add_file("/src/clip.mp4", 10, date("2024-06-15T14:30:00Z"));
+        env.add_file("/src/photo.jpg", 10, date("2024-06-15T14:30:00Z"));
+        env.sync();
+
+        assert_eq!(
+            env.files("/dst"),
+            [
+                PathBuf::from("/dst/2024/06/15/2024-06-15T143000.jpg"),
+                PathBuf::from("/dst/videos/2024/06/20240615_143000.mp4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn max_files_stops_the_sync_cleanly() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--max-files", "2"]);
+        for i in 0..5 {
+            env.add_file(
+                &format!("/src/{i}.jpg"),
+                10,
+                date(&format!("2024-02-0{}T00:00:00Z", i + 1)),
+            );
+        }
+        let result = env.sync();
+
+        assert_eq!(env.files("/dst").len(), 2);
+        assert_eq!(env.stats.copied_count(), 2);
+        assert_eq!(result.limit_reached.len(), 3);
+    }
+
+    #[test]
+    fn rename_only_renames_in_place() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--rename-only"]);
+        env.add_file("/src/DSC_0001.jpg", 10, date("2024-06-15T14:30:00Z"));
+        env.add_file("/src/nested/DSC_0002.jpg", 20, date("2024-06-15T14:30:00Z"));
+        env.add_file(
+            "/src/nested/2024-06-15T143000.jpg",
+            30,
+            date("2024-06-15T14:30:00.5Z"),
+        );
+        env.sync();
+
+        assert_eq!(
+            env.files("/src"),
+            [
+                PathBuf::from("/src/2024-06-15T143000.jpg"),
+                PathBuf::from("/src/nested/2024-06-15T143000.jpg"),
+                PathBuf::from("/src/nested/2024-06-15T143000_1.jpg"),
+            ]
+        );
+        assert!(!env.args.fs.exists(Path::new("/src/DSC_0001.jpg")));
+        assert!(!env.args.fs.exists(Path::new("/dst")));
+        assert_eq!(env.stats.renamed_count(), 2);
+    }
+
+    #[test]
+    fn move_removes_the_source() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--flat", "--move"]);
+        env.add_file("/src/DSC_0001.jpg", 10, date("2024-06-15T14:30:00Z"));
+        let result = env.sync();
+
+        assert!(env.files("/src").is_empty());
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024-06-15T143000.jpg")]
+        );
+        assert_eq!(env.stats.moved_count(), 1);
+        assert_eq!(env.stats.copied_count(), 0);
+        assert_eq!(result.total_files(&env.stats), 1);
+    }
+
+    #[test]
+    fn date_source_falls_back_to_the_birth_time() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let path = source.path().join("a.jpg");
+        let file = File::create(&path).unwrap();
+        file.set_modified(date("2020-01-01T00:00:00Z")).unwrap();
+        drop(file);
+        let std_metadata = std::fs::metadata(&path).unwrap();
+        let metadata = Metadata::from(std_metadata.clone());
+        assert_eq!(metadata.created(), std_metadata.created().ok());
+        let Some(created) = metadata.created() else {
+            // the filesystem doesn't record birth times
+            return;
+        };
+        assert_ne!(created, metadata.modified());
+
+        let args = std_args(
+            source.path(),
+            target.path(),
+            &["--date-source", "exif-then-created-then-modified"],
+        );
+        assert_eq!(file_creation_date(&args, &path, &metadata), Some(created));
+
+        let args = std_args(source.path(), target.path(), &["--date-source", "modified"]);
+        assert_eq!(
+            file_creation_date(&args, &path, &metadata),
+            Some(date("2020-01-01T00:00:00Z"))
+        );
+
+        let args = std_args(source.path(), target.path(), &["--date-source", "exif"]);
+        assert_eq!(file_creation_date(&args, &path, &metadata), None);
+    }
+
+    #[test]
+    fn dir_file_counts_per_output_directory() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--dir-stats"]);
+        for i in 0..12 {
+            env.add_file(
+                &format!("/src/{i}.jpg"),
+                10,
+                date(&format!("2024-0{}-01T00:{i:02}:00Z", i % 3 + 1)),
+            );
+        }
+        env.sync();
+
+        assert_eq!(env.ctx.dir_file_counts.len(), 3);
+        for month in 1..=3 {
+            assert_eq!(
+                env.ctx.dir_file_counts[Path::new(&format!("/dst/2024/0{month}/01"))],
+                4
+            );
+        }
+    }
+
+    #[test]
+    fn exclude_dir_skips_the_directory() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let thumbnails = source.path().join("album/@eaDir");
+        std::fs::create_dir_all(&thumbnails).unwrap();
+        let thumbnail = thumbnails.join("SYNOPHOTO_THUMB_M.jpg");
+        std::fs::write(&thumbnail, b"thumbnail").unwrap();
+        File::options()
+            .write(true)
+            .open(&thumbnail)
+            .unwrap()
+            .set_modified(date("2020-01-01T00:00:00Z"))
+            .unwrap();
+        std::fs::write(source.path().join("album/a.jpg"), b"photo").unwrap();
+        let args = std_args(
+            source.path(),
+            target.path(),
+            &[
+                "--date-source",
+                "modified",
+                "--flat",
+                "--exclude-dir",
+                "@eaDir",
+            ],
+        );
+        let mut ctx = AppContext::default();
+        let result = sync_media(&mut ctx, &args).unwrap();
+
+        let copied: Vec<Vec<u8>> = std::fs::read_dir(target.path())
+            .unwrap()
+            .map(|entry| std::fs::read(entry.unwrap().path()).unwrap())
+            .collect();
+        assert_eq!(copied, [b"photo".to_vec()]);
+        assert_eq!(args.stats.copied_count(), 1);
+        assert_eq!(args.stats.copied_size(), 5);
+        assert_eq!(args.stats.skipped_duplicates(), 0);
+        assert_eq!(result.total_files(&args.stats), 1);
+        assert!(result.unrecognized_files.is_empty());
+    }
+
+    #[test]
+    fn exclude_dir_in_memory() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--exclude-dir", "@eaDir"]);
+        env.add_file("/src/@eaDir/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file("/src/album/@eaDir/b.jpg", 10, date("2024-01-02T00:00:00Z"));
+        env.add_file("/src/album/c.jpg", 10, date("2024-01-03T00:00:00Z"));
+        env.sync();
+
+        assert_eq!(
+            env.files("/dst"),
+            [PathBuf::from("/dst/2024/01/03/2024-01-03T000000.jpg")]
+        );
+    }
+
+    #[test]
+    fn dry_run_diff_needs_no_source_and_target() {
+        let cli = [
+            "--dry-run-diff",
+            "before.json",
+            "--dry-run-diff",
+            "after.json",
+        ];
+        assert!(DryRunDiffArgs::requested(cli.map(String::from)));
+        assert!(!DryRunDiffArgs::requested(
+            ["/src", "/dst", "--dry-run", "--dry-run-diff", "before.json"].map(String::from)
+        ));
+
+        let args = DryRunDiffArgs::from_args(&["media-sync"], &cli).unwrap();
+        assert_eq!(args.dry_run_diff, ["before.json", "after.json"]);
+    }
+
+    #[traced_test]
+    #[test]
+    fn dry_run_diff_of_saved_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut before = ObjectMap::new();
+        before.insert(
+            PathBuf::from("/dst/a.jpg"),
+            (
+                Metadata::dummy_file(10).with_modified(date("2024-01-01T00:00:00Z")),
+                None,
+            ),
+        );
+        let mut after = ObjectMap::new();
+        after.insert(
+            PathBuf::from("/dst/b.jpg"),
+            (
+                Metadata::dummy_file(10).with_modified(date("2024-01-01T00:00:00Z")),
+                None,
+            ),
+        );
+        std::fs::write(dir.path().join("before.json"), before.to_json().unwrap()).unwrap();
+        std::fs::write(dir.path().join("after.json"), after.to_json().unwrap()).unwrap();
+
+        let encoding = OutputEncoding::Utf8Lossy;
+        let loaded_before =
+            load_dry_run_results(&dir.path().join("before.json"), encoding).unwrap();
+        let loaded_after = load_dry_run_results(&dir.path().join("after.json"), encoding).unwrap();
+        assert_eq!(loaded_before, before);
+        print_dry_run_diff(&loaded_before, &loaded_after, encoding);
+
+        logs_assert(|lines| logged(lines, "INFO", "- /dst/a.jpg"));
+        logs_assert(|lines| logged(lines, "INFO", "+ /dst/b.jpg"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_copied_as_symlinks() {
+        let library = tempfile::tempdir().unwrap();
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let original = library.path().join("a.jpg");
+        std::fs::write(&original, b"photo").unwrap();
+        std::os::unix::fs::symlink(&original, source.path().join("link.jpg")).unwrap();
+        let args = std_args(
+            source.path(),
+            target.path(),
+            &[
+                "--date-source",
+                "modified",
+                "--copy-symlinks-as-symlinks",
+                "--flat",
+            ],
+        );
+        let mut ctx = AppContext::default();
+        sync_media(&mut ctx, &args).unwrap();
+
+        let links: Vec<PathBuf> = std::fs::read_dir(target.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_symlink())
+            .collect();
+        let [link] = links.as_slice() else {
+            panic!("expected one symlink, found {links:?}");
+        };
+        assert_eq!(
+            std::fs::read_link(link).unwrap(),
+            original.canonicalize().unwrap()
+        );
+        assert_eq!(args.stats.symlinked_count(), 1);
+        assert_eq!(args.stats.copied_count(), 0);
+    }
+
+    #[test]
+    fn symlinks_in_memory_are_resolved_through_the_fs() {
+        let mut env = TestEnv::new(&[
+            "--date-source",
+            "modified",
+            "--copy-symlinks-as-symlinks",
+            "--flat",
+        ]);
+        let modified = date("2024-01-01T00:00:00Z");
+        env.add_file("/lib/a.jpg", 10, modified);
+        let mut link = Metadata::dummy_file(10).with_modified(modified);
+        link.mark_symlink();
+        env.objects.borrow_mut().insert(
+            PathBuf::from("/src/link.jpg"),
+            (link, Some(PathBuf::from("/lib/a.jpg"))),
+        );
+        env.sync();
+
+        let (meta, original) =
+            env.objects.borrow()[Path::new("/dst/2024-01-01T000000.jpg")].clone();
+        assert!(meta.is_symlink());
+        assert_eq!(original, Some(PathBuf::from("/lib/a.jpg")));
+        assert_eq!(env.stats.symlinked_count(), 1);
+    }
+
+    /// Wraps the test fs to simulate failures and listings that change while being iterated.
+    #[derive(Default)]
+    struct MockFs {
+        /// Fails every copy as if the target disk was full, counting the attempts.
+        disk_full: Option<Rc<Cell<u32>>>,
+        /// Lists files lazily from the objects, so renamed files are listed again.
+        live_listing: Option<Rc<RefCell<ObjectMap>>>,
+    }
+
+    struct MockFsWrapper {
+        fs: Box<dyn Fs>,
+        mock: MockFs,
+    }
+
+    impl MockFs {
+        fn install(self, env: &mut TestEnv) {
+            let fs = std::mem::replace(&mut env.args.fs, Box::new(fs::StdFs));
+            env.args.fs = Box::new(MockFsWrapper { fs, mock: self });
+        }
+    }
+
+    impl MockFsWrapper {
+        fn transfer<T>(&self, transfer: impl FnOnce() -> FsResult<T>) -> FsResult<T> {
+            match &self.mock.disk_full {
+                Some(attempts) => {
+                    attempts.set(attempts.get() + 1);
+                    Err(
+                        FsError::from(std::io::Error::from(std::io::ErrorKind::StorageFull))
+                            .context("[MockFs] Failed to copy"),
+                    )
+                }
+                None => transfer(),
+            }
+        }
+    }
+
+    impl Fs for MockFsWrapper {
+        fn name(&self) -> String {
+            format!("MockFs({})", self.fs.name())
+        }
+        fn create_dir_all(&self, path: &Path) -> FsResult<()> {
+            self.fs.create_dir_all(path)
+        }
+        fn metadata(&self, path: &Path) -> FsResult<Metadata> {
+            self.fs.metadata(path)
+        }
+        fn copy(&self, from: &Path, to: &Path) -> FsResult<u64> {
+            self.transfer(|| self.fs.copy(from, to))
+        }
+        fn copy_with_sha256(&self, from: &Path, to: &Path) -> FsResult<(u64, String)> {
+            self.transfer(|| self.fs.copy_with_sha256(from, to))
+        }
+        fn rename(&self, from: &Path, to: &Path) -> FsResult<()> {
+            self.fs.rename(from, to)
+        }
+        fn move_file(&self, from: &Path, to: &Path) -> FsResult<()> {
+            self.transfer(|| self.fs.move_file(from, to))
+        }
+        fn symlink(&self, original: &Path, link: &Path) -> FsResult<()> {
+            self.fs.symlink(original, link)
+        }
+        fn replace(&self, from: &Path, to: &Path) -> FsResult<()> {
+            self.fs.replace(from, to)
+        }
+        fn write_bytes(&self, path: &Path, data: &[u8]) -> FsResult<()> {
+            self.fs.write_bytes(path, data)
+        }
+        fn list_dir(&self, path: &Path, excluded_dirs: &HashSet<String>) -> FsResult<DirIterator> {
+            let Some(objects) = &self.mock.live_listing else {
+                return self.fs.list_dir(path, excluded_dirs);
+            };
+            let objects = Rc::clone(objects);
+            let root = path.to_path_buf();
+            let mut listed = HashSet::new();
+            Ok(Box::new(std::iter::from_fn(move || {
+                let objects = objects.borrow();
+                let (path, (metadata, _)) = objects
+                    .iter()
+                    .filter(|(path, (metadata, _))| {
+                        metadata.is_file() && path.starts_with(&root) && !listed.contains(*path)
+                    })
+                    .min_by_key(|(path, _)| *path)?;
+                listed.insert(path.clone());
+                Some(Ok(fs::DirEntry {
+                    path: path.clone(),
+                    metadata: metadata.clone(),
+                }))
+            })))
+        }
+        fn exists(&self, path: &Path) -> bool {
+            self.fs.exists(path)
+        }
+        fn sha256(&self, path: &Path) -> FsResult<String> {
+            self.fs.sha256(path)
+        }
+        fn read_to_string(&self, path: &Path) -> FsResult<String> {
+            self.fs.read_to_string(path)
+        }
+        fn canonicalize(&self, path: &Path) -> FsResult<PathBuf> {
+            self.fs.canonicalize(path)
+        }
+    }
+
+    #[test]
+    fn full_disk_aborts_the_sync() {
+        let mut env = TestEnv::new(&["--date-source", "modified"]);
+        for i in 0..3 {
+            env.add_file(
+                &format!("/src/{i}.jpg"),
+                10,
+                date(&format!("2024-02-0{}T00:00:00Z", i + 1)),
+            );
+        }
+        let attempts = Rc::new(Cell::new(0));
+        MockFs {
+            disk_full: Some(Rc::clone(&attempts)),
+            ..MockFs::default()
+        }
+        .install(&mut env);
+
+        let err = sync_media(&mut env.ctx, &env.args).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Target disk is full, the sync has been aborted"
+        );
+        assert!(is_disk_full(&err));
+        assert!(!is_limit_exceeded(&err));
+        assert_eq!(env.stats.copied_count(), 0);
+        assert!(env.files("/dst").is_empty());
+        // the first failure stops the sync instead of trying the remaining files
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn disk_full_is_found_through_the_context_chain() {
+        let e = anyhow::Error::from(FsError::from(std::io::Error::from(
+            std::io::ErrorKind::StorageFull,
+        )))
+        .context("Failed to process file [/src/a.jpg]");
+        assert!(is_disk_full(&e));
+        assert!(!is_disk_full(&anyhow::anyhow!("other")));
+    }
+
+    #[test]
+    fn rename_only_lists_the_source_before_renaming() {
+        let mut env = TestEnv::new(&["--date-source", "modified", "--rename-only"]);
+        for i in 0..3 {
+            env.add_file(
+                &format!("/src/DSC_000{i}.jpg"),
+                10,
+                date(&format!("2024-02-0{}T00:00:00Z", i + 1)),
+            );
+        }
+        MockFs {
+            live_listing: Some(Rc::clone(&env.objects)),
+            ..MockFs::default()
+        }
+        .install(&mut env);
+        let result = env.sync();
+
+        assert_eq!(env.stats.renamed_count(), 3);
+        assert_eq!(env.stats.skipped_duplicates(), 0);
+        assert_eq!(result.total_files(&env.stats), 3);
+    }
 }
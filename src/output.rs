@@ -0,0 +1,80 @@
+use anyhow::bail;
+use std::fmt::{self, Write as _};
+use std::path::Path;
+use std::str::FromStr;
+
+/// How paths are written to logs and the dry run output.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub(crate) enum OutputEncoding {
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD`.
+    #[default]
+    Utf8Lossy,
+    /// Source paths that are not valid UTF-8 are rejected, see [`check_path_encoding`].
+    Utf8Strict,
+    /// Invalid UTF-8 bytes and `%` are written as `%XX`.
+    PercentEncoded,
+}
+
+impl FromStr for OutputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf8-lossy" => Ok(Self::Utf8Lossy),
+            "utf8-strict" => Ok(Self::Utf8Strict),
+            "percent-encoded" => Ok(Self::PercentEncoded),
+            _ => Err(format!(
+                "unknown output encoding [{s}], expected one of: utf8-lossy, utf8-strict, percent-encoded"
+            )),
+        }
+    }
+}
+
+/// Displays a path using the given [`OutputEncoding`].
+pub(crate) struct PathFormatter<'a> {
+    path: &'a Path,
+    encoding: OutputEncoding,
+}
+
+impl<'a> PathFormatter<'a> {
+    pub(crate) fn new(path: &'a Path, encoding: OutputEncoding) -> Self {
+        Self { path, encoding }
+    }
+}
+
+impl fmt::Display for PathFormatter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.encoding {
+            // strict paths are checked beforehand, Display cannot fail gracefully
+            OutputEncoding::Utf8Lossy | OutputEncoding::Utf8Strict => {
+                f.pad(&self.path.to_string_lossy())
+            }
+            OutputEncoding::PercentEncoded => {
+                let mut encoded = String::new();
+                for chunk in self.path.as_os_str().as_encoded_bytes().utf8_chunks() {
+                    for c in chunk.valid().chars() {
+                        match c {
+                            '%' => encoded.push_str("%25"),
+                            c => encoded.push(c),
+                        }
+                    }
+                    for byte in chunk.invalid() {
+                        write!(encoded, "%{byte:02X}")?;
+                    }
+                }
+                f.pad(&encoded)
+            }
+        }
+    }
+}
+
+/// Fails on paths that are not valid UTF-8 in [`OutputEncoding::Utf8Strict`] mode.
+pub(crate) fn check_path_encoding(path: &Path, encoding: OutputEncoding) -> anyhow::Result<()> {
+    if encoding == OutputEncoding::Utf8Strict && path.to_str().is_none() {
+        bail!(
+            "Path is not valid UTF-8: [{}]",
+            PathFormatter::new(path, OutputEncoding::PercentEncoded)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use crate::fs::Metadata;
+use crate::output::{OutputEncoding, PathFormatter};
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Answer to a conflict prompt in `--interactive` mode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum ConflictChoice {
+    Skip,
+    Overwrite,
+    Rename,
+    Quit,
+}
+
+/// Asks the user what to do when a different file already exists in the target.
+pub(crate) trait UserPrompt {
+    fn resolve_conflict(
+        &self,
+        source: &Path,
+        source_metadata: &Metadata,
+        target: &Path,
+        target_metadata: &Metadata,
+    ) -> anyhow::Result<ConflictChoice>;
+
+    /// Asks a yes/no question.
+    fn confirm(&self, question: &str) -> anyhow::Result<bool>;
+}
+
+/// Prompts on stdout and reads the answer from stdin.
+pub(crate) struct StdinPrompt {
+    pub(crate) encoding: OutputEncoding,
+}
+
+impl UserPrompt for StdinPrompt {
+    fn resolve_conflict(
+        &self,
+        source: &Path,
+        source_metadata: &Metadata,
+        target: &Path,
+        target_metadata: &Metadata,
+    ) -> anyhow::Result<ConflictChoice> {
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "Target file already exists:")?;
+        writeln!(
+            stdout,
+            "  source: {}",
+            describe(source, source_metadata, self.encoding)
+        )?;
+        writeln!(
+            stdout,
+            "  target: {}",
+            describe(target, target_metadata, self.encoding)
+        )?;
+
+        loop {
+            match ask(&mut stdout, "[s]kip, [o]verwrite, [r]ename, [q]uit? ")?.as_str() {
+                "s" => return Ok(ConflictChoice::Skip),
+                "o" => return Ok(ConflictChoice::Overwrite),
+                "r" => return Ok(ConflictChoice::Rename),
+                "q" => return Ok(ConflictChoice::Quit),
+                _ => continue,
+            }
+        }
+    }
+
+    fn confirm(&self, question: &str) -> anyhow::Result<bool> {
+        let mut stdout = io::stdout().lock();
+        loop {
+            match ask(&mut stdout, &format!("{question} [y]es, [n]o? "))?.as_str() {
+                "y" => return Ok(true),
+                "n" => return Ok(false),
+                _ => continue,
+            }
+        }
+    }
+}
+
+/// Prints the question and reads a trimmed answer from stdin.
+fn ask(stdout: &mut impl Write, question: &str) -> anyhow::Result<String> {
+    write!(stdout, "{question}")?;
+    stdout.flush()?;
+    let mut answer = String::new();
+    if io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .with_context(|| "Failed to read the answer")?
+        == 0
+    {
+        bail!("Stdin has been closed while waiting for an answer");
+    }
+    Ok(answer.trim().to_string())
+}
+
+fn describe(path: &Path, metadata: &Metadata, encoding: OutputEncoding) -> String {
+    let modified: DateTime<Utc> = metadata.modified().into();
+    format!(
+        "{} ({} bytes, modified {})",
+        PathFormatter::new(path, encoding),
+        metadata.len(),
+        modified.format("%Y-%m-%d %H:%M:%S")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn describe_uses_the_output_encoding() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"/dst/a\xff.jpg"));
+        let metadata = Metadata::dummy_file(10);
+        let description = describe(path, &metadata, OutputEncoding::PercentEncoded);
+        assert!(
+            description.starts_with("/dst/a%FF.jpg (10 bytes, modified "),
+            "{description}"
+        );
+    }
+}
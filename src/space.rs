@@ -0,0 +1,16 @@
+use std::io;
+use std::path::Path;
+
+/// Reports the free space on the filesystem of a path, used by `--min-free-space`.
+pub(crate) trait SpaceProvider {
+    fn available_space(&self, path: &Path) -> io::Result<u64>;
+}
+
+/// Asks the operating system.
+pub(crate) struct SystemSpace;
+
+impl SpaceProvider for SystemSpace {
+    fn available_space(&self, path: &Path) -> io::Result<u64> {
+        fs2::available_space(path)
+    }
+}
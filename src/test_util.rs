@@ -0,0 +1,191 @@
+//! In-memory sync setup for tests: a [`DryFs`] over an empty filesystem syncing `/src` into `/dst`.
+
+use crate::fs::dry::ObjectMap;
+use crate::fs::limited::LimitedFs;
+use crate::fs::stat::{LocalStatFs, Stats};
+use crate::fs::{
+    DirIterator, DryFs, ErrorContextFs, FsError, FsResult, Metadata, ReadonlyFs, StdFs,
+};
+use crate::prompt::{ConflictChoice, UserPrompt};
+use crate::space::SpaceProvider;
+use crate::{sync_media, AppContext, Args, RawArgs, SyncResult};
+use anyhow::anyhow;
+use argh::FromArgs;
+use chrono::DateTime;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::SystemTime;
+
+/// Filesystem that contains only the root directory.
+pub(crate) struct EmptyFs;
+
+impl ReadonlyFs for EmptyFs {
+    fn name(&self) -> String {
+        "EmptyFs".to_string()
+    }
+
+    fn metadata(&self, path: &Path) -> FsResult<Metadata> {
+        if self.exists(path) {
+            Ok(Metadata::dummy_folder())
+        } else {
+            Err(FsError::NotFound(anyhow!(
+                "[{}] does not exist",
+                path.display()
+            )))
+        }
+    }
+
+    fn list_dir(&self, _path: &Path, _excluded_dirs: &HashSet<String>) -> FsResult<DirIterator> {
+        Ok(Box::new(std::iter::empty()))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.parent().is_none()
+    }
+
+    fn sha256(&self, path: &Path) -> FsResult<String> {
+        self.metadata(path)?;
+        Err(FsError::Other(anyhow!(
+            "[{}] is a directory",
+            path.display()
+        )))
+    }
+
+    fn read_to_string(&self, path: &Path) -> FsResult<String> {
+        self.metadata(path)?;
+        Err(FsError::Other(anyhow!(
+            "[{}] is a directory",
+            path.display()
+        )))
+    }
+
+    fn canonicalize(&self, path: &Path) -> FsResult<PathBuf> {
+        self.metadata(path)?;
+        Ok(path.to_path_buf())
+    }
+}
+
+/// Prompt giving the same answers every time.
+pub(crate) struct MockPrompt {
+    pub(crate) choice: ConflictChoice,
+    pub(crate) confirm: bool,
+}
+
+impl UserPrompt for MockPrompt {
+    fn resolve_conflict(
+        &self,
+        _source: &Path,
+        _source_metadata: &Metadata,
+        _target: &Path,
+        _target_metadata: &Metadata,
+    ) -> anyhow::Result<ConflictChoice> {
+        Ok(self.choice)
+    }
+
+    fn confirm(&self, _question: &str) -> anyhow::Result<bool> {
+        Ok(self.confirm)
+    }
+}
+
+/// Reports a fixed amount of free space, `None` fails the check.
+pub(crate) struct FixedSpace(pub(crate) Option<u64>);
+
+impl SpaceProvider for FixedSpace {
+    fn available_space(&self, _path: &Path) -> std::io::Result<u64> {
+        self.0
+            .ok_or_else(|| std::io::Error::other("no space information"))
+    }
+}
+
+/// Creates `Args` for syncing `source` into `target` on the real filesystem.
+pub(crate) fn std_args(source: &Path, target: &Path, options: &[&str]) -> Args {
+    let mut cli = vec![source.to_str().unwrap(), target.to_str().unwrap()];
+    cli.extend_from_slice(options);
+    let raw = RawArgs::from_args(&["media-sync"], &cli).unwrap();
+    let stats = Rc::new(Stats::default());
+    let fs = LocalStatFs::new(
+        LimitedFs::new(ErrorContextFs::new(StdFs), raw.max_files, raw.max_bytes),
+        Rc::clone(&stats),
+    );
+    Args::new(raw, Box::new(fs), stats).unwrap()
+}
+
+/// Parses an RFC 3339 date like `2024-06-15T14:30:00Z`.
+pub(crate) fn date(s: &str) -> SystemTime {
+    DateTime::parse_from_rfc3339(s).unwrap().into()
+}
+
+pub(crate) struct TestEnv {
+    pub(crate) ctx: AppContext,
+    pub(crate) args: Args,
+    pub(crate) objects: Rc<RefCell<ObjectMap>>,
+    pub(crate) stats: Rc<Stats>,
+}
+
+impl TestEnv {
+    /// Creates the environment with the given options, the source and target are added automatically.
+    pub(crate) fn new(options: &[&str]) -> Self {
+        let mut cli = vec!["/src", "/dst"];
+        cli.extend_from_slice(options);
+        let raw = RawArgs::from_args(&["media-sync"], &cli).unwrap();
+
+        let objects = Rc::new(RefCell::new(ObjectMap::new()));
+        let stats = Rc::new(Stats::default());
+        let fs = LocalStatFs::new(
+            LimitedFs::new(
+                DryFs::new(EmptyFs, Rc::clone(&objects)),
+                raw.max_files,
+                raw.max_bytes,
+            ),
+            Rc::clone(&stats),
+        );
+        let args = Args::new(raw, Box::new(fs), Rc::clone(&stats)).unwrap();
+        let env = Self {
+            ctx: AppContext::default(),
+            args,
+            objects,
+            stats,
+        };
+        env.add_dir("/src");
+        env
+    }
+
+    pub(crate) fn add_dir(&self, path: &str) {
+        self.args.fs.create_dir_all(Path::new(path)).unwrap();
+    }
+
+    /// Adds a file together with its parent directories.
+    pub(crate) fn add_file(&self, path: &str, len: u64, modified: SystemTime) {
+        let path = PathBuf::from(path);
+        self.args.fs.create_dir_all(path.parent().unwrap()).unwrap();
+        self.objects.borrow_mut().insert(
+            path,
+            (Metadata::dummy_file(len).with_modified(modified), None),
+        );
+    }
+
+    pub(crate) fn sync(&mut self) -> SyncResult {
+        sync_media(&mut self.ctx, &self.args).unwrap()
+    }
+
+    /// Sorted paths of all files under `root`.
+    pub(crate) fn files(&self, root: &str) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self
+            .args
+            .fs
+            .list_dir(Path::new(root), &HashSet::new())
+            .unwrap()
+            .map(Result::unwrap)
+            .filter(|entry| entry.metadata.is_file())
+            .map(|entry| entry.path)
+            .collect();
+        files.sort();
+        files
+    }
+
+    pub(crate) fn metadata(&self, path: &str) -> Metadata {
+        self.args.fs.metadata(Path::new(path)).unwrap()
+    }
+}
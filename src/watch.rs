@@ -0,0 +1,152 @@
+use crate::{sync_file, AppContext, Args, SyncResult};
+use anyhow::Context;
+use notify::event::ModifyKind;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Files are processed only after no new events arrived for this long,
+/// so a file that is still being written is not picked up halfway.
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_secs(1);
+
+enum WatchEvent {
+    Fs(notify::Result<Event>),
+    Shutdown,
+}
+
+/// Processes files that appear or change in the source directory until Ctrl-C is pressed.
+pub(crate) fn watch_source(
+    ctx: &mut AppContext,
+    args: &Args,
+    result: &mut SyncResult,
+) -> anyhow::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+
+    let shutdown_sender = sender.clone();
+    ctrlc::set_handler(move || {
+        let _ = shutdown_sender.send(WatchEvent::Shutdown);
+    })
+    .with_context(|| "Failed to set Ctrl-C handler")?;
+
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(WatchEvent::Fs(event));
+    })
+    .with_context(|| "Failed to create filesystem watcher")?;
+    watcher
+        .watch(&args.source, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch [{}]", args.fmt_path(&args.source)))?;
+
+    info!(
+        "Watching [{}] for changes, press Ctrl-C to stop",
+        args.fmt_path(&args.source)
+    );
+    let mut pending = HashSet::new();
+    loop {
+        match receiver.recv_timeout(DEBOUNCE_TIMEOUT) {
+            Ok(WatchEvent::Shutdown) | Err(RecvTimeoutError::Disconnected) => {
+                info!("Stopping watch mode");
+                break;
+            }
+            Ok(WatchEvent::Fs(Err(e))) => warn!("Watch error: {e:?}"),
+            Ok(WatchEvent::Fs(Ok(event))) => pending.extend(changed_files(event)),
+            Err(RecvTimeoutError::Timeout) => {
+                sync_changed_files(ctx, args, pending.drain(), result);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Syncs the changed source files, errors are logged so that watching can continue.
+fn sync_changed_files(
+    ctx: &mut AppContext,
+    args: &Args,
+    paths: impl IntoIterator<Item = PathBuf>,
+    result: &mut SyncResult,
+) {
+    for path in paths {
+        let is_file = args.fs.metadata(&path).is_ok_and(|meta| meta.is_file());
+        if !is_file || path.starts_with(&args.target) {
+            continue;
+        }
+        if let Err(e) = sync_file(ctx, args, &path, result) {
+            error!("{e:?}");
+        }
+    }
+}
+
+fn changed_files(event: Event) -> impl Iterator<Item = PathBuf> {
+    let relevant = matches!(
+        event.kind,
+        EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any)
+    );
+    event.paths.into_iter().filter(move |_| relevant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{date, TestEnv};
+    use notify::event::{AccessKind, CreateKind, DataChange, RemoveKind};
+
+    fn event(kind: EventKind, path: &str) -> Event {
+        Event::new(kind).add_path(PathBuf::from(path))
+    }
+
+    #[test]
+    fn only_created_and_modified_files_are_synced() {
+        let mut env = TestEnv::new(&["--date-source", "modified"]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.add_file("/src/b.jpg", 10, date("2024-01-02T00:00:00Z"));
+        env.add_file("/src/c.jpg", 10, date("2024-01-03T00:00:00Z"));
+        env.add_file("/src/d.jpg", 10, date("2024-01-04T00:00:00Z"));
+        let events = [
+            event(EventKind::Create(CreateKind::File), "/src/a.jpg"),
+            event(
+                EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+                "/src/b.jpg",
+            ),
+            event(EventKind::Access(AccessKind::Read), "/src/c.jpg"),
+            event(EventKind::Remove(RemoveKind::File), "/src/d.jpg"),
+        ];
+        let paths: Vec<PathBuf> = events.into_iter().flat_map(changed_files).collect();
+        assert_eq!(
+            paths,
+            [PathBuf::from("/src/a.jpg"), PathBuf::from("/src/b.jpg")]
+        );
+
+        let mut result = SyncResult::default();
+        env.args.fs.create_dir_all(&env.args.target).unwrap();
+        sync_changed_files(&mut env.ctx, &env.args, paths, &mut result);
+        assert_eq!(
+            env.files("/dst"),
+            [
+                PathBuf::from("/dst/2024/01/01/2024-01-01T000000.jpg"),
+                PathBuf::from("/dst/2024/01/02/2024-01-02T000000.jpg"),
+            ]
+        );
+        assert_eq!(env.stats.copied_count(), 2);
+    }
+
+    #[test]
+    fn directories_missing_files_and_the_target_are_ignored() {
+        let mut env = TestEnv::new(&["--date-source", "modified"]);
+        env.add_file("/src/a.jpg", 10, date("2024-01-01T00:00:00Z"));
+        env.add_dir("/src/nested");
+        env.sync();
+
+        let mut result = SyncResult::default();
+        let paths = [
+            PathBuf::from("/src/nested"),
+            PathBuf::from("/src/missing.jpg"),
+            PathBuf::from("/dst/2024/01/01/2024-01-01T000000.jpg"),
+        ];
+        sync_changed_files(&mut env.ctx, &env.args, paths, &mut result);
+        assert_eq!(env.stats.copied_count(), 1);
+        assert_eq!(env.files("/dst").len(), 1);
+    }
+}